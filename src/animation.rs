@@ -0,0 +1,289 @@
+/*!
+  Frame based animation of sprites.
+
+  An `AnimationClip` is a list of frames which each show a set of textures
+  for a while. An `Animator` keeps track of which frame of a clip is shown,
+  reports the events of the frames it passes and updates the textures of a
+  sprite in all render steps at once.
+*/
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use render_steps::RenderSteps;
+use sprite::{Sprite, SpriteTexture};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopMode
+{
+    /// Play the frames once and stay on the last frame
+    Once,
+    /// Start over from the first frame after the last frame
+    Loop,
+    /// Play the frames forwards and then backwards
+    PingPong,
+}
+
+#[derive(Clone)]
+pub struct AnimationFrame
+{
+    pub textures: HashMap<RenderSteps, SpriteTexture>,
+    /// How long the frame is shown in seconds
+    pub duration: f32,
+    /// Names of the events that happen when the frame starts being shown,
+    /// for example footsteps
+    pub events: Vec<String>,
+}
+
+impl AnimationFrame
+{
+    pub fn new(textures: HashMap<RenderSteps, SpriteTexture>, duration: f32) -> AnimationFrame
+    {
+        AnimationFrame {
+            textures: textures,
+            duration: duration,
+            events: vec!(),
+        }
+    }
+
+    pub fn with_event(mut self, event: &str) -> AnimationFrame
+    {
+        self.events.push(event.to_string());
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct AnimationClip
+{
+    pub name: String,
+    pub frames: Vec<AnimationFrame>,
+    pub mode: LoopMode,
+}
+
+impl AnimationClip
+{
+    pub fn new(name: &str, frames: Vec<AnimationFrame>, mode: LoopMode) -> AnimationClip
+    {
+        AnimationClip {
+            name: name.to_string(),
+            frames: frames,
+            mode: mode,
+        }
+    }
+
+    /**
+      The length of the clip in seconds when played from the first to the
+      last frame
+    */
+    pub fn duration(&self) -> f32
+    {
+        self.frames.iter().map(|frame| frame.duration).sum()
+    }
+}
+
+
+pub struct Animator
+{
+    clip: Rc<AnimationClip>,
+    frame: usize,
+    time_in_frame: f32,
+    /// True while a ping pong clip is playing backwards
+    reversed: bool,
+    finished: bool,
+    /// The events of the first frame are reported by the first update
+    started: bool,
+}
+
+impl Animator
+{
+    pub fn new(clip: Rc<AnimationClip>) -> Animator
+    {
+        Animator {
+            clip: clip,
+            frame: 0,
+            time_in_frame: 0.,
+            reversed: false,
+            finished: false,
+            started: false,
+        }
+    }
+
+    /**
+      Starts playing `clip` from the beginning
+    */
+    pub fn play(&mut self, clip: Rc<AnimationClip>)
+    {
+        *self = Animator::new(clip);
+    }
+
+    pub fn clip(&self) -> &AnimationClip
+    {
+        &self.clip
+    }
+
+    pub fn current_frame(&self) -> usize
+    {
+        self.frame
+    }
+
+    /**
+      True when a clip played with `LoopMode::Once` has reached the end of
+      its last frame
+    */
+    pub fn is_finished(&self) -> bool
+    {
+        self.finished
+    }
+
+    /**
+      Advances the animation by `delta_time` seconds and returns the events
+      of the frames that started during that time, in order
+    */
+    pub fn update(&mut self, delta_time: f32) -> Vec<String>
+    {
+        let mut events = vec!();
+        if self.clip.frames.is_empty()
+        {
+            return events;
+        }
+
+        if !self.started
+        {
+            self.started = true;
+            events.extend(self.clip.frames[0].events.iter().cloned());
+        }
+
+        // Without this, clips where all frames are instant would never stop
+        // advancing
+        if self.finished || self.clip.duration() <= 0.
+        {
+            return events;
+        }
+
+        self.time_in_frame += delta_time;
+        while self.time_in_frame >= self.clip.frames[self.frame].duration
+        {
+            let next = match self.next_frame()
+            {
+                Some(next) => next,
+                None => {
+                    self.finished = true;
+                    self.time_in_frame = self.clip.frames[self.frame].duration;
+                    break;
+                }
+            };
+
+            self.time_in_frame -= self.clip.frames[self.frame].duration;
+            self.frame = next;
+            events.extend(self.clip.frames[next].events.iter().cloned());
+        }
+
+        events
+    }
+
+    /**
+      Returns the frame after the current one, or None if the clip has ended.
+      Changes direction when a ping pong clip reaches either end
+    */
+    fn next_frame(&mut self) -> Option<usize>
+    {
+        let last = self.clip.frames.len() - 1;
+
+        match self.clip.mode
+        {
+            LoopMode::Once if self.frame == last => None,
+            LoopMode::Once => Some(self.frame + 1),
+            LoopMode::Loop => Some(if self.frame == last { 0 } else { self.frame + 1 }),
+            LoopMode::PingPong if last == 0 => Some(0),
+            LoopMode::PingPong => {
+                if (self.reversed && self.frame == 0) || (!self.reversed && self.frame == last)
+                {
+                    self.reversed = !self.reversed;
+                }
+
+                Some(if self.reversed { self.frame - 1 } else { self.frame + 1 })
+            }
+        }
+    }
+
+    /**
+      Shows the textures of the current frame on `sprite`
+    */
+    pub fn apply(&self, sprite: &mut Sprite)
+    {
+        if let Some(frame) = self.clip.frames.get(self.frame)
+        {
+            sprite.set_textures(&frame.textures);
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn clip(durations: &[f32], mode: LoopMode) -> Rc<AnimationClip>
+    {
+        let frames = durations.iter()
+            .enumerate()
+            .map(|(i, duration)| {
+                AnimationFrame::new(HashMap::new(), *duration).with_event(&format!("frame{}", i))
+            })
+            .collect();
+
+        Rc::new(AnimationClip::new("test", frames, mode))
+    }
+
+    fn frames_after_steps(animator: &mut Animator, steps: usize, delta_time: f32) -> Vec<usize>
+    {
+        (0..steps)
+            .map(|_| {
+                animator.update(delta_time);
+                animator.current_frame()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn looping_clips_start_over()
+    {
+        let mut animator = Animator::new(clip(&[0.1, 0.1, 0.1], LoopMode::Loop));
+
+        assert_eq!(frames_after_steps(&mut animator, 5, 0.1), vec!(1, 2, 0, 1, 2));
+        assert!(!animator.is_finished());
+    }
+
+    #[test]
+    fn clips_played_once_stay_on_the_last_frame()
+    {
+        let mut animator = Animator::new(clip(&[0.1, 0.2], LoopMode::Once));
+
+        assert_eq!(frames_after_steps(&mut animator, 4, 0.1), vec!(1, 1, 1, 1));
+        assert!(animator.is_finished());
+    }
+
+    #[test]
+    fn ping_pong_clips_reverse_at_the_ends()
+    {
+        let mut animator = Animator::new(clip(&[1., 1., 1.], LoopMode::PingPong));
+
+        assert_eq!(frames_after_steps(&mut animator, 6, 1.), vec!(1, 2, 1, 0, 1, 2));
+    }
+
+    #[test]
+    fn events_of_skipped_frames_are_reported_in_order()
+    {
+        let mut animator = Animator::new(clip(&[0.1, 0.1, 0.1], LoopMode::Loop));
+
+        assert_eq!(animator.update(0.05), vec!("frame0".to_string()));
+        assert_eq!(animator.update(0.), Vec::<String>::new());
+        assert_eq!(
+            animator.update(0.3),
+            vec!("frame1".to_string(), "frame2".to_string(), "frame0".to_string())
+        );
+    }
+}
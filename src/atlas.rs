@@ -0,0 +1,344 @@
+/*!
+  Texture atlases which pack many small images into a few large textures.
+
+  Images are placed on shelves: rows as tall as the tallest image on them
+  which are filled from left to right. Images are packed from tallest to
+  shortest so that the images on a shelf have similar heights.
+*/
+use glium::backend::Facade;
+use glium::texture::{RawImage2d, SrgbTexture2d};
+
+use image::{self, GenericImage, RgbaImage};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use render_steps::RenderSteps;
+use rendering::RenderStep;
+
+
+/// Empty pixels between packed images so that filtering does not bleed
+/// neighbouring images into each other
+pub const DEFAULT_PADDING: u32 = 1;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement
+{
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packing
+{
+    /// The placement of every packed size, in the same order as the sizes
+    pub placements: Vec<Option<Placement>>,
+    pub page_count: usize,
+    /// Indices of the sizes that are too large to fit on a page
+    pub unfit: Vec<usize>,
+}
+
+struct Shelf
+{
+    y: u32,
+    height: u32,
+    width_used: u32,
+}
+
+struct Page
+{
+    shelves: Vec<Shelf>,
+    height_used: u32,
+}
+
+/**
+  Packs rectangles of `sizes` onto as many pages of `page_size` as needed
+  with `padding` pixels between them
+*/
+pub fn pack(sizes: &[(u32, u32)], page_size: (u32, u32), padding: u32) -> Packing
+{
+    let mut placements = vec!(None; sizes.len());
+    let mut unfit = vec!();
+    let mut pages: Vec<Page> = vec!();
+
+    let mut order = (0..sizes.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| sizes[b].1.cmp(&sizes[a].1).then(sizes[b].0.cmp(&sizes[a].0)));
+
+    for index in order
+    {
+        let (width, height) = (sizes[index].0 + padding, sizes[index].1 + padding);
+        if width > page_size.0 + padding || height > page_size.1 + padding
+        {
+            unfit.push(index);
+            continue;
+        }
+
+        let mut placement = None;
+        for (page_index, page) in pages.iter_mut().enumerate()
+        {
+            placement = place_on_page(page, page_index, (width, height), page_size, padding);
+            if placement.is_some()
+            {
+                break;
+            }
+        }
+
+        if placement.is_none()
+        {
+            let mut page = Page { shelves: vec!(), height_used: 0 };
+            let page_index = pages.len();
+            placement = place_on_page(&mut page, page_index, (width, height), page_size, padding);
+            pages.push(page);
+        }
+
+        placements[index] = placement;
+    }
+
+    unfit.sort();
+    Packing {
+        placements,
+        page_count: pages.len(),
+        unfit,
+    }
+}
+
+/**
+  Places a rectangle of `size`, including padding, on an existing shelf of
+  `page` or on a new shelf if there is room for one
+*/
+fn place_on_page(
+            page: &mut Page,
+            page_index: usize,
+            size: (u32, u32),
+            page_size: (u32, u32),
+            padding: u32
+        ) -> Option<Placement>
+{
+    // The padding after the last image on a shelf or page can be outside it
+    let (available_width, available_height) = (page_size.0 + padding, page_size.1 + padding);
+
+    for shelf in page.shelves.iter_mut()
+    {
+        if size.1 <= shelf.height && shelf.width_used + size.0 <= available_width
+        {
+            let placement = Placement { page: page_index, x: shelf.width_used, y: shelf.y };
+            shelf.width_used += size.0;
+            return Some(placement);
+        }
+    }
+
+    if page.height_used + size.1 <= available_height
+    {
+        let placement = Placement { page: page_index, x: 0, y: page.height_used };
+        page.shelves.push(Shelf { y: page.height_used, height: size.1, width_used: size.0 });
+        page.height_used += size.1;
+        return Some(placement);
+    }
+
+    None
+}
+
+
+/**
+  The part of an atlas page that contains one image. `uv_rect` is the
+  position and size of the image in texture coordinates
+*/
+#[derive(Clone)]
+pub struct AtlasRegion
+{
+    pub page: Arc<SrgbTexture2d>,
+    pub uv_rect: (f32, f32, f32, f32),
+    pub size: (u32, u32),
+}
+
+pub struct TextureAtlas
+{
+    pages: Vec<Arc<SrgbTexture2d>>,
+    regions: HashMap<String, AtlasRegion>,
+}
+
+impl TextureAtlas
+{
+    /**
+      Packs `images` into pages of `page_size` and uploads them. Returns the
+      atlas and the names of the images that did not fit
+    */
+    pub fn build(
+                facade: &Facade,
+                images: Vec<(String, RgbaImage)>,
+                page_size: (u32, u32)
+            ) -> (TextureAtlas, Vec<String>)
+    {
+        let sizes = images.iter().map(|&(_, ref image)| image.dimensions()).collect::<Vec<_>>();
+        let packing = pack(&sizes, page_size, DEFAULT_PADDING);
+
+        let mut page_images = (0..packing.page_count)
+            .map(|_| RgbaImage::new(page_size.0, page_size.1))
+            .collect::<Vec<_>>();
+        for (&(_, ref image), placement) in images.iter().zip(packing.placements.iter())
+        {
+            if let Some(placement) = *placement
+            {
+                page_images[placement.page].copy_from(image, placement.x, placement.y);
+            }
+        }
+
+        let pages = page_images.into_iter()
+            .map(|image| {
+                let dimensions = image.dimensions();
+                let raw = RawImage2d::from_raw_rgba(image.into_raw(), dimensions);
+                Arc::new(SrgbTexture2d::new(facade, raw).unwrap())
+            })
+            .collect::<Vec<_>>();
+
+        let mut regions = HashMap::new();
+        let mut unfit = vec!();
+        for ((name, image), placement) in images.into_iter().zip(packing.placements.into_iter())
+        {
+            match placement
+            {
+                Some(placement) => {
+                    let size = image.dimensions();
+                    let uv_rect = (
+                        placement.x as f32 / page_size.0 as f32,
+                        placement.y as f32 / page_size.1 as f32,
+                        size.0 as f32 / page_size.0 as f32,
+                        size.1 as f32 / page_size.1 as f32
+                    );
+                    regions.insert(name, AtlasRegion {
+                        page: pages[placement.page].clone(),
+                        uv_rect,
+                        size
+                    });
+                }
+                None => unfit.push(name)
+            }
+        }
+
+        (TextureAtlas { pages, regions }, unfit)
+    }
+
+    /**
+      Packs all PNG images in `directory`. Images are named after their file
+      name without the extension, so `media/lamp_emissive.png` is called
+      `lamp_emissive`
+    */
+    pub fn load_directory(facade: &Facade, directory: &Path, page_size: (u32, u32))
+        -> io::Result<(TextureAtlas, Vec<String>)>
+    {
+        let mut paths = fs::read_dir(directory)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.retain(|path| path.extension().map(|extension| extension == "png").unwrap_or(false));
+        paths.sort();
+
+        let mut images = vec!();
+        for path in paths
+        {
+            let name = match path.file_stem().and_then(|stem| stem.to_str())
+            {
+                Some(name) => name.to_string(),
+                None => continue
+            };
+            let image = image::open(&path)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+                .to_rgba();
+            images.push((name, image));
+        }
+
+        Ok(TextureAtlas::build(facade, images, page_size))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AtlasRegion>
+    {
+        self.regions.get(name)
+    }
+
+    /**
+      Returns the variant of image `name` for `step`. The diffuse variant is
+      the image itself while other steps use `<name>_<step name>`, for example
+      `lamp_emissive`
+    */
+    pub fn get_for_step(&self, name: &str, step: &RenderSteps) -> Option<&AtlasRegion>
+    {
+        match *step
+        {
+            RenderSteps::Diffuse => self.get(name),
+            _ => self.get(&format!("{}_{}", name, step.name()))
+        }
+    }
+
+    pub fn pages(&self) -> &[Arc<SrgbTexture2d>]
+    {
+        &self.pages
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn overlaps(a: (Placement, (u32, u32)), b: (Placement, (u32, u32))) -> bool
+    {
+        let ((a, a_size), (b, b_size)) = (a, b);
+        a.page == b.page
+            && a.x < b.x + b_size.0 && b.x < a.x + a_size.0
+            && a.y < b.y + b_size.1 && b.y < a.y + a_size.1
+    }
+
+    #[test]
+    fn images_are_packed_without_overlapping()
+    {
+        let sizes = [(32, 32), (16, 16), (32, 16), (8, 30), (32, 32), (20, 10)];
+
+        let packing = pack(&sizes, (80, 80), 1);
+
+        assert_eq!(packing.page_count, 1);
+        assert!(packing.unfit.is_empty());
+
+        let placed = packing.placements.iter()
+            .zip(sizes.iter())
+            .map(|(placement, size)| (placement.unwrap(), *size))
+            .collect::<Vec<_>>();
+        for (i, a) in placed.iter().enumerate()
+        {
+            assert!(a.0.x + (a.1).0 <= 80 && a.0.y + (a.1).1 <= 80);
+            for b in placed.iter().skip(i + 1)
+            {
+                assert!(!overlaps(*a, *b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn full_pages_spill_onto_new_pages()
+    {
+        let sizes = [(32, 32); 5];
+
+        let packing = pack(&sizes, (64, 64), 0);
+
+        assert_eq!(packing.page_count, 2);
+        assert_eq!(packing.placements[4], Some(Placement{page: 1, x: 0, y: 0}));
+    }
+
+    #[test]
+    fn images_larger_than_a_page_are_reported()
+    {
+        let sizes = [(16, 16), (100, 8), (8, 65), (64, 64)];
+
+        let packing = pack(&sizes, (64, 64), 1);
+
+        assert_eq!(packing.unfit, vec!(1, 2));
+        assert!(packing.placements[0].is_some());
+        assert_eq!(packing.placements[3], Some(Placement{page: 0, x: 0, y: 0}));
+        assert_eq!(packing.placements[0].unwrap().page, 1);
+    }
+}
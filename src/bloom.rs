@@ -0,0 +1,174 @@
+/*!
+  Glow around emissive sprites.
+
+  The emissive target is thresholded and downsampled into a smaller texture
+  which is then blurred with a gaussian kernel, first horizontally and then
+  vertically. Blurring the axes separately makes the cost linear in the
+  radius, and the smaller texture makes large radii cheap.
+*/
+use glium::texture::texture2d::Texture2d;
+use glium::texture::{MipmapsOption, UncompressedFloatFormat};
+use glium::backend::Facade;
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler, SamplerWrapFunction};
+use glium::{Program, VertexBuffer};
+use glium;
+use glium::Surface;
+
+use glium_types::Vertex;
+use rendering;
+
+
+pub const THRESHOLD_FRAGMENT_SHADER: &'static str = include_str!("shaders/bloom_threshold_frag.fs");
+pub const BLUR_FRAGMENT_SHADER: &'static str = include_str!("shaders/bloom_blur_frag.fs");
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings
+{
+    /// Emissive colours are only bloomed by how much their brightest channel
+    /// exceeds this
+    pub threshold: f32,
+    /// Radius of the blur in screen pixels
+    pub radius: f32,
+    /// Standard deviation of the gaussian in screen pixels
+    pub sigma: f32,
+    pub strength: f32,
+}
+
+impl Default for BloomSettings
+{
+    fn default() -> BloomSettings
+    {
+        BloomSettings {
+            threshold: 0.,
+            radius: 12.,
+            sigma: 4.,
+            strength: 1.,
+        }
+    }
+}
+
+
+fn linear_sampler(texture: &Texture2d) -> Sampler<Texture2d>
+{
+    texture.sampled()
+        .magnify_filter(MagnifySamplerFilter::Linear)
+        .minify_filter(MinifySamplerFilter::Linear)
+        .wrap_function(SamplerWrapFunction::Clamp)
+}
+
+fn float_texture(facade: &Facade, resolution: (u32, u32)) -> Texture2d
+{
+    Texture2d::empty_with_format(
+            facade,
+            UncompressedFloatFormat::F16F16F16F16,
+            MipmapsOption::NoMipmap,
+            resolution.0,
+            resolution.1
+        ).unwrap()
+}
+
+
+pub struct Bloom
+{
+    threshold_shader: Program,
+    blur_shader: Program,
+
+    downsample: u32,
+    resolution: (u32, u32),
+
+    bright_texture: Texture2d,
+    horizontal_texture: Texture2d,
+    result_texture: Texture2d,
+}
+
+impl Bloom
+{
+    /**
+      Creates the textures for blooming a target of size `resolution`. The
+      blur is done at `1 / downsample` of that resolution
+    */
+    pub fn new(facade: &Facade, resolution: (u32, u32), downsample: u32) -> Bloom
+    {
+        let downsample = downsample.max(1);
+        let resolution = (
+                (resolution.0 / downsample).max(1),
+                (resolution.1 / downsample).max(1)
+            );
+
+        let threshold_shader = Program::from_source(
+                    facade,
+                    rendering::VERTEX_SHADER,
+                    THRESHOLD_FRAGMENT_SHADER,
+                    None
+                ).unwrap();
+        let blur_shader = Program::from_source(
+                    facade,
+                    rendering::VERTEX_SHADER,
+                    BLUR_FRAGMENT_SHADER,
+                    None
+                ).unwrap();
+
+        Bloom {
+            threshold_shader: threshold_shader,
+            blur_shader: blur_shader,
+
+            downsample: downsample,
+            resolution: resolution,
+
+            bright_texture: float_texture(facade, resolution),
+            horizontal_texture: float_texture(facade, resolution),
+            result_texture: float_texture(facade, resolution),
+        }
+    }
+
+    /**
+      Blooms `source` into the texture returned by `result`. `vertices` is a
+      quad covering the whole target
+    */
+    pub fn apply(&self, source: &Texture2d, vertices: &VertexBuffer<Vertex>, settings: &BloomSettings)
+    {
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        let texel_size = (1. / self.resolution.0 as f32, 1. / self.resolution.1 as f32);
+
+        let radius = (settings.radius / self.downsample as f32).ceil();
+        let sigma = (settings.sigma / self.downsample as f32).max(0.001);
+
+        self.bright_texture.as_surface().draw(
+                vertices,
+                &indices,
+                &self.threshold_shader,
+                &uniform!{
+                    source: linear_sampler(source),
+                    texel_size: texel_size,
+                    threshold: settings.threshold
+                },
+                &Default::default()
+            ).unwrap();
+
+        let passes = [
+            (&self.bright_texture, &self.horizontal_texture, (texel_size.0, 0.)),
+            (&self.horizontal_texture, &self.result_texture, (0., texel_size.1)),
+        ];
+        for &(from, to, direction) in &passes
+        {
+            to.as_surface().draw(
+                    vertices,
+                    &indices,
+                    &self.blur_shader,
+                    &uniform!{
+                        source: linear_sampler(from),
+                        direction: direction,
+                        radius: radius,
+                        sigma: sigma
+                    },
+                    &Default::default()
+                ).unwrap();
+        }
+    }
+
+    pub fn result(&self) -> &Texture2d
+    {
+        &self.result_texture
+    }
+}
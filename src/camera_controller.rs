@@ -0,0 +1,408 @@
+/*!
+  A camera which follows a target around a level.
+
+  The camera keeps a goal point which only moves when the target leaves the
+  dead zone around it. The goal is offset in the direction the target is
+  moving so that more of the level ahead of the target is visible, and the
+  camera moves towards the goal with critically damped smoothing which never
+  overshoots. Finally the view is clamped to the bounds of the level.
+
+  Everything here is in world pixels. Updates only depend on the timestep so
+  the same inputs always move the camera the same way.
+*/
+extern crate nalgebra as na;
+
+use camera_state::CameraState;
+use grid::{self, Grid};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FollowSettings
+{
+    /// Half the size of the area around the goal that the target can move
+    /// in without moving the camera
+    pub dead_zone: na::Vector2<f32>,
+    /// Roughly the time it takes the camera to reach the goal, in seconds
+    pub smooth_time: f32,
+    /// The camera looks ahead by the velocity of the target times this
+    pub look_ahead_time: f32,
+    /// The largest look ahead distance along each axis
+    pub max_look_ahead: na::Vector2<f32>,
+}
+
+impl Default for FollowSettings
+{
+    fn default() -> FollowSettings
+    {
+        FollowSettings {
+            dead_zone: na::Vector2::new(32., 48.),
+            smooth_time: 0.25,
+            look_ahead_time: 0.3,
+            max_look_ahead: na::Vector2::new(96., 32.),
+        }
+    }
+}
+
+
+/**
+  Moves `current` towards `target` like a critically damped spring with the
+  velocity `velocity`. Roughly reaches the target in `smooth_time` and never
+  passes it
+*/
+pub fn smooth_damp(current: f32, target: f32, velocity: &mut f32, smooth_time: f32, dt: f32) -> f32
+{
+    if dt <= 0.
+    {
+        return current;
+    }
+
+    let smooth_time = smooth_time.max(0.0001);
+    let omega = 2. / smooth_time;
+
+    // Approximation of exp(-omega * dt) which is good enough for small steps
+    let x = omega * dt;
+    let decay = 1. / (1. + x + 0.48 * x * x + 0.235 * x * x * x);
+
+    let change = current - target;
+    let temp = (*velocity + omega * change) * dt;
+    *velocity = (*velocity - omega * temp) * decay;
+
+    let result = target + (change + temp) * decay;
+
+    if (target - current > 0.) == (result > target)
+    {
+        *velocity = 0.;
+        return target;
+    }
+    result
+}
+
+/**
+  Moves `goal` as little as possible so that `target` is within `dead_zone`
+  of it
+*/
+pub fn apply_dead_zone(goal: na::Vector2<f32>, target: na::Vector2<f32>, dead_zone: na::Vector2<f32>)
+    -> na::Vector2<f32>
+{
+    fn axis(goal: f32, target: f32, dead_zone: f32) -> f32
+    {
+        if target > goal + dead_zone
+        {
+            target - dead_zone
+        }
+        else if target < goal - dead_zone
+        {
+            target + dead_zone
+        }
+        else
+        {
+            goal
+        }
+    }
+
+    na::Vector2::new(
+        axis(goal.x, target.x, dead_zone.x),
+        axis(goal.y, target.y, dead_zone.y)
+    )
+}
+
+/**
+  Returns the center closest to `center` for which a view of `view_size`
+  stays within `bounds`. A view larger than the bounds is centered on them
+*/
+pub fn clamp_to_bounds(
+            center: na::Vector2<f32>,
+            view_size: na::Vector2<f32>,
+            bounds: (na::Vector2<f32>, na::Vector2<f32>)
+        ) -> na::Vector2<f32>
+{
+    fn axis(center: f32, view_size: f32, min: f32, max: f32) -> f32
+    {
+        if view_size >= max - min
+        {
+            (min + max) / 2.
+        }
+        else
+        {
+            center.max(min + view_size / 2.).min(max - view_size / 2.)
+        }
+    }
+
+    let (min, max) = bounds;
+    na::Vector2::new(
+        axis(center.x, view_size.x, min.x, max.x),
+        axis(center.y, view_size.y, min.y, max.y)
+    )
+}
+
+/**
+  The area covered by the blocks of `grid` in world pixels
+*/
+pub fn grid_bounds(grid: &Grid) -> Option<(na::Vector2<f32>, na::Vector2<f32>)>
+{
+    grid.bounding_box().map(|(min, max)| {
+        (grid::cell_to_world(min), grid::cell_to_world(max + na::Vector2::new(1, 1)))
+    })
+}
+
+
+pub struct CameraController
+{
+    settings: FollowSettings,
+    center: na::Vector2<f32>,
+    velocity: na::Vector2<f32>,
+    goal: na::Vector2<f32>,
+    look_ahead: na::Vector2<f32>,
+    look_ahead_velocity: na::Vector2<f32>,
+    bounds: Option<(na::Vector2<f32>, na::Vector2<f32>)>,
+}
+
+impl CameraController
+{
+    pub fn new(settings: FollowSettings, center: na::Vector2<f32>) -> CameraController
+    {
+        CameraController {
+            settings: settings,
+            center: center,
+            velocity: na::zero(),
+            goal: center,
+            look_ahead: na::zero(),
+            look_ahead_velocity: na::zero(),
+            bounds: None,
+        }
+    }
+
+    pub fn settings(&self) -> &FollowSettings
+    {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: FollowSettings)
+    {
+        self.settings = settings;
+    }
+
+    /**
+      Limits the view to an area in world pixels, or removes the limit
+    */
+    pub fn set_bounds(&mut self, bounds: Option<(na::Vector2<f32>, na::Vector2<f32>)>)
+    {
+        self.bounds = bounds;
+    }
+
+    /**
+      Limits the view to the blocks of `grid`
+    */
+    pub fn set_bounds_from_grid(&mut self, grid: &Grid)
+    {
+        self.bounds = grid_bounds(grid);
+    }
+
+    /**
+      Moves the camera straight to `center` without smoothing, for example
+      when a level starts
+    */
+    pub fn snap_to(&mut self, center: na::Vector2<f32>)
+    {
+        self.center = center;
+        self.goal = center;
+        self.velocity = na::zero();
+        self.look_ahead = na::zero();
+        self.look_ahead_velocity = na::zero();
+    }
+
+    /**
+      The point in the middle of the view in world pixels
+    */
+    pub fn center(&self) -> na::Vector2<f32>
+    {
+        self.center
+    }
+
+    /**
+      Moves the camera `dt` seconds towards `target` which is moving with
+      `target_velocity`. `view_size` is the size of the visible area in
+      world pixels
+    */
+    pub fn update(
+                &mut self,
+                target: na::Vector2<f32>,
+                target_velocity: na::Vector2<f32>,
+                view_size: na::Vector2<f32>,
+                dt: f32
+            )
+    {
+        let settings = self.settings;
+
+        self.goal = apply_dead_zone(self.goal, target, settings.dead_zone);
+
+        let wanted_look_ahead = target_velocity * settings.look_ahead_time;
+        let wanted_look_ahead = na::Vector2::new(
+            wanted_look_ahead.x.max(-settings.max_look_ahead.x).min(settings.max_look_ahead.x),
+            wanted_look_ahead.y.max(-settings.max_look_ahead.y).min(settings.max_look_ahead.y)
+        );
+        self.look_ahead = na::Vector2::new(
+            smooth_damp(self.look_ahead.x, wanted_look_ahead.x, &mut self.look_ahead_velocity.x, settings.smooth_time, dt),
+            smooth_damp(self.look_ahead.y, wanted_look_ahead.y, &mut self.look_ahead_velocity.y, settings.smooth_time, dt)
+        );
+
+        let wanted = self.goal + self.look_ahead;
+        let mut center = na::Vector2::new(
+            smooth_damp(self.center.x, wanted.x, &mut self.velocity.x, settings.smooth_time, dt),
+            smooth_damp(self.center.y, wanted.y, &mut self.velocity.y, settings.smooth_time, dt)
+        );
+
+        if let Some(bounds) = self.bounds
+        {
+            let clamped = clamp_to_bounds(center, view_size, bounds);
+            // Stop pushing against the edge so the camera leaves it as soon
+            // as the target turns around
+            if clamped.x != center.x
+            {
+                self.velocity.x = 0.;
+            }
+            if clamped.y != center.y
+            {
+                self.velocity.y = 0.;
+            }
+            center = clamped;
+        }
+
+        self.center = center;
+    }
+
+    /**
+      Points `camera_state` at the center of the controller when drawing to
+      a target of `target_size` pixels
+    */
+    pub fn apply(&self, camera_state: &mut CameraState, target_size: (u32, u32))
+    {
+        let (width, height) = (target_size.0 as f32, target_size.1 as f32);
+
+        // The inverse of the window scaling matrix, which puts world pixel
+        // (w/4, h/4) in the middle of an unmoved camera
+        camera_state.set_position(na::Vector2::new(
+            2. * self.center.x / width - 0.5,
+            0.5 - 2. * self.center.y / height
+        ));
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const DT: f32 = 1. / 60.;
+
+    fn view() -> na::Vector2<f32>
+    {
+        na::Vector2::new(200., 100.)
+    }
+
+    fn settings() -> FollowSettings
+    {
+        FollowSettings {
+            dead_zone: na::Vector2::new(10., 10.),
+            smooth_time: 0.2,
+            look_ahead_time: 0.,
+            max_look_ahead: na::zero(),
+        }
+    }
+
+    #[test]
+    fn smooth_damp_reaches_target_without_overshooting()
+    {
+        let mut velocity = 0.;
+        let mut value = 0.;
+        for _ in 0..120
+        {
+            let next = smooth_damp(value, 100., &mut velocity, 0.2, DT);
+            assert!(next >= value && next <= 100.);
+            value = next;
+        }
+        assert!((value - 100.).abs() < 0.01);
+    }
+
+    #[test]
+    fn targets_inside_the_dead_zone_do_not_move_the_camera()
+    {
+        let mut controller = CameraController::new(settings(), na::zero());
+
+        for _ in 0..60
+        {
+            controller.update(na::Vector2::new(8., -9.), na::zero(), view(), DT);
+        }
+        assert_eq!(controller.center(), na::zero());
+
+        for _ in 0..300
+        {
+            controller.update(na::Vector2::new(50., 0.), na::zero(), view(), DT);
+        }
+        assert!((controller.center().x - 40.).abs() < 0.01);
+        assert_eq!(controller.center().y, 0.);
+    }
+
+    #[test]
+    fn camera_looks_ahead_of_moving_targets()
+    {
+        let mut leading = CameraController::new(
+            FollowSettings {
+                look_ahead_time: 0.5,
+                max_look_ahead: na::Vector2::new(30., 30.),
+                .. settings()
+            },
+            na::zero()
+        );
+        let mut trailing = CameraController::new(settings(), na::zero());
+
+        let velocity = na::Vector2::new(100., 0.);
+        let mut target = na::zero::<na::Vector2<f32>>();
+        for _ in 0..600
+        {
+            target = target + velocity * DT;
+            leading.update(target, velocity, view(), DT);
+            trailing.update(target, velocity, view(), DT);
+        }
+
+        // Both cameras lag behind the target by the same amount so the
+        // difference is the look ahead, which is limited to 30
+        let lead = leading.center().x - trailing.center().x;
+        assert!((lead - 30.).abs() < 0.5, "lead was {}", lead);
+        assert_eq!(leading.center().y, 0.);
+    }
+
+    #[test]
+    fn view_is_clamped_to_bounds()
+    {
+        let bounds = (na::Vector2::new(0., 0.), na::Vector2::new(1000., 80.));
+        let mut controller = CameraController::new(settings(), na::Vector2::new(500., 40.));
+        controller.set_bounds(Some(bounds));
+
+        for _ in 0..600
+        {
+            controller.update(na::Vector2::new(-400., 300.), na::zero(), view(), DT);
+        }
+
+        // The view is taller than the bounds so it is centered vertically
+        assert_eq!(controller.center(), na::Vector2::new(100., 40.));
+    }
+
+    #[test]
+    fn camera_is_centered_on_the_controller()
+    {
+        let mut controller = CameraController::new(settings(), na::zero());
+        controller.snap_to(na::Vector2::new(300., 120.));
+
+        let mut camera_state = CameraState::new();
+        camera_state.set_zoom(2.);
+        controller.apply(&mut camera_state, (800, 600));
+
+        let matrix = camera_state.get_matrix((800, 600))
+            * ::drawing_util::get_window_scaling_matrix((800., 600.));
+        let clip = matrix * na::Vector4::new(300., 120., 0., 1.);
+        assert!(clip.x.abs() < 0.0001 && clip.y.abs() < 0.0001);
+    }
+}
@@ -0,0 +1,186 @@
+/*!
+  Trauma based screen shake.
+
+  Things like explosions add trauma between 0 and 1 which decays over time.
+  The camera is moved and turned by smooth noise scaled by the square of the
+  trauma, so small amounts of trauma barely move the camera while large
+  amounts shake it violently. The shake is set separately from the position
+  of the camera so it adds on top of whatever moves the camera, like a
+  `CameraController`.
+*/
+extern crate nalgebra as na;
+
+use camera_state::CameraState;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShakeSettings
+{
+    /// The largest offset along each axis in world pixels
+    pub max_offset: na::Vector2<f32>,
+    /// The largest rotation in radians
+    pub max_angle: f32,
+    /// How many times per second the shake changes direction, roughly
+    pub frequency: f32,
+    /// Trauma removed per second
+    pub decay: f32,
+}
+
+impl Default for ShakeSettings
+{
+    fn default() -> ShakeSettings
+    {
+        ShakeSettings {
+            max_offset: na::Vector2::new(24., 24.),
+            max_angle: 0.08,
+            frequency: 15.,
+            decay: 1.,
+        }
+    }
+}
+
+
+/**
+  A pseudo random number between -1 and 1 for each integer `x` and `seed`
+*/
+fn hash_noise(x: i32, seed: u32) -> f32
+{
+    let mut n = (x as u32).wrapping_mul(374761393) ^ seed.wrapping_mul(668265263);
+    n = (n ^ (n >> 13)).wrapping_mul(1274126177);
+    n = n ^ (n >> 16);
+    (n as f32 / ::std::u32::MAX as f32) * 2. - 1.
+}
+
+/**
+  Noise between -1 and 1 which changes smoothly with `x`. Different seeds
+  give unrelated noise
+*/
+pub fn smooth_noise(x: f32, seed: u32) -> f32
+{
+    let start = x.floor();
+    let t = x - start;
+    let t = t * t * (3. - 2. * t);
+
+    let a = hash_noise(start as i32, seed);
+    let b = hash_noise(start as i32 + 1, seed);
+    a + (b - a) * t
+}
+
+
+pub struct CameraShake
+{
+    settings: ShakeSettings,
+    trauma: f32,
+    time: f32,
+}
+
+impl CameraShake
+{
+    pub fn new(settings: ShakeSettings) -> CameraShake
+    {
+        CameraShake {
+            settings: settings,
+            trauma: 0.,
+            time: 0.,
+        }
+    }
+
+    /**
+      Adds trauma, the total is limited to 1
+    */
+    pub fn add_trauma(&mut self, amount: f32)
+    {
+        self.trauma = (self.trauma + amount).max(0.).min(1.);
+    }
+
+    pub fn trauma(&self) -> f32
+    {
+        self.trauma
+    }
+
+    pub fn update(&mut self, dt: f32)
+    {
+        self.time += dt;
+        self.trauma = (self.trauma - self.settings.decay * dt).max(0.);
+    }
+
+    /**
+      How far the camera is moved in world pixels
+    */
+    pub fn offset(&self) -> na::Vector2<f32>
+    {
+        let shake = self.shake();
+        let x = self.time * self.settings.frequency;
+        na::Vector2::new(
+            self.settings.max_offset.x * shake * smooth_noise(x, 0),
+            self.settings.max_offset.y * shake * smooth_noise(x, 1)
+        )
+    }
+
+    /**
+      How far the camera is turned in radians
+    */
+    pub fn angle(&self) -> f32
+    {
+        self.settings.max_angle * self.shake() * smooth_noise(self.time * self.settings.frequency, 2)
+    }
+
+    pub fn apply(&self, camera_state: &mut CameraState)
+    {
+        camera_state.set_shake(self.offset(), self.angle());
+    }
+
+    fn shake(&self) -> f32
+    {
+        self.trauma * self.trauma
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn noise_is_smooth_and_bounded()
+    {
+        let mut previous = smooth_noise(0., 3);
+        for i in 1..10000
+        {
+            let value = smooth_noise(i as f32 * 0.01, 3);
+            assert!(value >= -1. && value <= 1.);
+            assert!((value - previous).abs() < 0.05, "jump at {}", i);
+            previous = value;
+        }
+    }
+
+    #[test]
+    fn trauma_decays_and_stops_the_shake()
+    {
+        let mut shake = CameraShake::new(ShakeSettings { decay: 0.5, .. Default::default() });
+        shake.add_trauma(0.7);
+        shake.add_trauma(0.7);
+        assert_eq!(shake.trauma(), 1.);
+
+        let mut moved = false;
+        for _ in 0..60
+        {
+            shake.update(1. / 60.);
+            let offset = shake.offset();
+            assert!(offset.x.abs() <= 24. && offset.y.abs() <= 24.);
+            moved |= offset.x != 0. || offset.y != 0.;
+        }
+        assert!(moved);
+        assert!((shake.trauma() - 0.5).abs() < 0.001);
+
+        for _ in 0..70
+        {
+            shake.update(1. / 60.);
+        }
+        assert_eq!(shake.trauma(), 0.);
+        assert_eq!(shake.offset(), na::zero());
+        assert_eq!(shake.angle(), 0.);
+    }
+}
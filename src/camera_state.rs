@@ -1,9 +1,17 @@
 extern crate nalgebra as na;
 
+#[derive(Clone, Debug)]
 pub struct CameraState
 {
     position: na::Vector2<f32>,
-    zoom: f32
+    zoom: f32,
+    /// Counter clockwise rotation of the camera in radians. The world turns
+    /// the other way on the screen
+    angle: f32,
+    /// Extra movement of the camera in world pixels and radians which is
+    /// added on top of the position and angle, used for screen shake
+    shake_offset: na::Vector2<f32>,
+    shake_angle: f32,
 }
 
 impl CameraState
@@ -12,19 +20,41 @@ impl CameraState
     {
         CameraState {
             position: na::zero(),
-            zoom: 1.
+            zoom: 1.,
+            angle: 0.,
+            shake_offset: na::zero(),
+            shake_angle: 0.,
         }
     }
 
-    pub fn get_matrix(&self) -> na::Matrix4<f32>
+    /**
+      The matrix which moves and rotates the world after it has been scaled
+      by the window scaling matrix of a target of `target_size`. Zooming and
+      rotation happen around the middle of the target
+    */
+    pub fn get_matrix(&self, target_size: (u32, u32)) -> na::Matrix4<f32>
     {
-        let offset = -self.position * self.zoom;
+        let (width, height) = (target_size.0 as f32, target_size.1 as f32);
+        let aspect_ratio = width / height;
+
+        // The shake offset is in world pixels while the position is in the
+        // units of the window scaling matrix
+        let center = self.position + na::Vector2::new(
+                2. * self.shake_offset.x / width,
+                -2. * self.shake_offset.y / height
+            );
+
+        // Rotating in clip space would stretch the world on targets which
+        // are not square, so the rotation is corrected by the aspect ratio
+        let angle = -(self.angle + self.shake_angle);
+        let (sin, cos) = (angle.sin() * self.zoom, angle.cos() * self.zoom);
+        let (a, b, c, d) = (cos, -sin / aspect_ratio, sin * aspect_ratio, cos);
 
         na::Matrix4::new(
-                self.zoom, 0.       , 0., offset.x,
-                0.       , self.zoom, 0., offset.y,
-                0.       , 0.       , 1., 0.,
-                0.       , 0.       , 0., 1.,
+                a , b , 0., -(a * center.x + b * center.y),
+                c , d , 0., -(c * center.x + d * center.y),
+                0., 0., 1., 0.,
+                0., 0., 0., 1.,
             )
     }
 
@@ -42,6 +72,30 @@ impl CameraState
     {
         return self.position;
     }
+
+    pub fn get_zoom(&self) -> f32
+    {
+        self.zoom
+    }
+
+    pub fn set_angle(&mut self, angle: f32)
+    {
+        self.angle = angle;
+    }
+
+    pub fn get_angle(&self) -> f32
+    {
+        self.angle
+    }
+
+    /**
+      Sets the screen shake which is added to the position and angle
+    */
+    pub fn set_shake(&mut self, offset: na::Vector2<f32>, angle: f32)
+    {
+        self.shake_offset = offset;
+        self.shake_angle = angle;
+    }
 }
 
 
@@ -51,37 +105,89 @@ mod tests
     extern crate nalgebra as na;
     use super::CameraState;
 
+    use std::f32::consts::PI;
+
+    fn transform(state: &CameraState, target_size: (u32, u32), x: f32, y: f32) -> na::Vector2<f32>
+    {
+        let result = state.get_matrix(target_size) * na::Vector4::new(x, y, 0., 1.);
+        na::Vector2::new(result.x, result.y)
+    }
+
+    fn assert_close(a: na::Vector2<f32>, b: na::Vector2<f32>)
+    {
+        assert!((a.x - b.x).abs() < 0.0001 && (a.y - b.y).abs() < 0.0001, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_same_matrix(a: &CameraState, b: &CameraState, target_size: (u32, u32))
+    {
+        let (a, b): ([[f32; 4]; 4], [[f32; 4]; 4]) = (
+            *a.get_matrix(target_size).as_ref(),
+            *b.get_matrix(target_size).as_ref()
+        );
+        for (column_a, column_b) in a.iter().zip(b.iter())
+        {
+            for (value_a, value_b) in column_a.iter().zip(column_b.iter())
+            {
+                assert!((value_a - value_b).abs() < 0.0001, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
     #[test]
-    fn matrix_translation_test()
+    fn unmoved_camera_does_not_change_anything()
     {
-        let vector = na::Vector4::new(
-                1.,
-                2.,
-                0.,
-                1.
-            );
+        let state = CameraState::new();
+
+        assert_eq!(state.get_matrix((800, 600)), na::one::<na::Matrix4<f32>>());
+    }
 
+    #[test]
+    fn position_is_moved_to_the_middle_of_the_target()
+    {
         let mut state = CameraState::new();
-        state.set_position(na::Vector2::new(5., 5.));
+        state.set_position(na::Vector2::new(0.3, -0.2));
+        state.set_zoom(2.);
+        state.set_angle(1.);
 
-        let scaling_matrix = na::one::<na::Matrix4<f32>>();
-        let translated = (scaling_matrix + state.get_position_matrix((1,1))) * vector;
+        assert_close(transform(&state, (800, 600), 0.3, -0.2), na::zero());
+    }
+
+    #[test]
+    fn zooming_scales_around_the_position()
+    {
+        let mut state = CameraState::new();
+        state.set_position(na::Vector2::new(0.5, 0.5));
+        state.set_zoom(2.);
 
-        assert_eq!(translated, na::Vector4::new(-4., -3., 0., 1.));
+        assert_close(transform(&state, (800, 600), 0.75, 0.25), na::Vector2::new(0.5, -0.5));
     }
 
-    fn matrix_translation_with_window_test()
+    #[test]
+    fn rotation_keeps_pixel_distances_on_non_square_targets()
     {
         let mut state = CameraState::new();
-        state.set_position(na::Vector2::new(100., 50.));
+        state.set_angle(PI / 2.);
 
-        let desired_matrix = na::Matrix4::new(
-                0., 0., 0., -1. ,
-                0., 0., 0., -0.5,
-                0., 0., 0., 0.  ,
-                0., 0., 0., 0.
-            );
+        // 0.1 of the width of an 800 pixel target is 40 pixels, which is 0.2
+        // of the height of a 400 pixel target
+        let rotated = transform(&state, (800, 400), 0.1, 0.);
+        assert_close(rotated, na::Vector2::new(0., -0.2));
+    }
 
-        assert_eq!(desired_matrix, state.get_position_matrix((100, 100)));
+    #[test]
+    fn shake_adds_to_the_position_and_angle()
+    {
+        let mut shaken = CameraState::new();
+        shaken.set_position(na::Vector2::new(0.1, 0.1));
+        shaken.set_angle(0.2);
+        // 100 world pixels is a quarter of the width of the window scaling
+        // matrix of an 800 pixel target
+        shaken.set_shake(na::Vector2::new(100., 60.), 0.1);
+
+        let mut moved = CameraState::new();
+        moved.set_position(na::Vector2::new(0.1 + 0.25, 0.1 - 0.2));
+        moved.set_angle(0.3);
+
+        assert_same_matrix(&shaken, &moved, (800, 600));
     }
 }
@@ -0,0 +1,157 @@
+/*!
+  Saving rendered frames as PNG images, either as single screenshots or as
+  numbered sequences that can be turned into animations.
+*/
+use glium;
+
+use image::RgbaImage;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use drawing_util;
+use rendering::RenderStep;
+
+
+/**
+  Reads back the last frame shown on the display. Call this after the frame
+  has been finished
+*/
+pub fn capture_frame(display: &glium::Display) -> RgbaImage
+{
+    drawing_util::raw_to_image(display.read_front_buffer())
+}
+
+/**
+  Saves `frame` as `<name>.png` in `directory`, and every image in `steps` as
+  `<name>_<step name>.png`. Returns the paths of the saved images
+*/
+pub fn save_screenshot<T: RenderStep>(
+            directory: &Path,
+            name: &str,
+            frame: &RgbaImage,
+            steps: &HashMap<T, RgbaImage>
+        ) -> io::Result<Vec<PathBuf>>
+{
+    fs::create_dir_all(directory)?;
+
+    let mut images = vec!((directory.join(format!("{}.png", name)), frame));
+
+    let mut step_images = steps.iter()
+        .map(|(step, image)| (directory.join(format!("{}_{}.png", name, step.name())), image))
+        .collect::<Vec<_>>();
+    step_images.sort_by(|a, b| a.0.cmp(&b.0));
+    images.extend(step_images);
+
+    let mut paths = vec!();
+    for (path, image) in images
+    {
+        image.save(&path)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+
+/**
+  Returns the path of frame `index` of a sequence. The numbers are padded so
+  that the files sort in the order they were recorded
+*/
+pub fn sequence_frame_path(directory: &Path, prefix: &str, index: u32) -> PathBuf
+{
+    directory.join(format!("{}_{:05}.png", prefix, index))
+}
+
+/**
+  Saves every frame passed to `record` as the next image of a numbered
+  sequence
+*/
+pub struct FrameSequence
+{
+    directory: PathBuf,
+    prefix: String,
+    next_index: u32,
+}
+
+impl FrameSequence
+{
+    pub fn new(directory: &Path, prefix: &str) -> io::Result<FrameSequence>
+    {
+        fs::create_dir_all(directory)?;
+
+        Ok(FrameSequence {
+            directory: directory.to_path_buf(),
+            prefix: prefix.to_string(),
+            next_index: 0,
+        })
+    }
+
+    pub fn record(&mut self, frame: &RgbaImage) -> io::Result<PathBuf>
+    {
+        let path = sequence_frame_path(&self.directory, &self.prefix, self.next_index);
+        frame.save(&path)?;
+
+        self.next_index += 1;
+        Ok(path)
+    }
+
+    pub fn frame_count(&self) -> u32
+    {
+        self.next_index
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use image::Rgba;
+    use render_steps::RenderSteps;
+    use std::env;
+
+    fn test_directory(name: &str) -> PathBuf
+    {
+        let directory = env::temp_dir().join(format!("thief_capture_{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn screenshots_include_the_render_steps()
+    {
+        let directory = test_directory("screenshot");
+        let frame = RgbaImage::from_pixel(2, 2, Rgba{data: [255, 0, 0, 255]});
+        let mut steps = HashMap::new();
+        steps.insert(RenderSteps::Emissive, RgbaImage::new(2, 2));
+        steps.insert(RenderSteps::Diffuse, RgbaImage::new(2, 2));
+
+        let paths = save_screenshot(&directory, "shot", &frame, &steps).unwrap();
+
+        assert_eq!(paths, vec!(
+                directory.join("shot.png"),
+                directory.join("shot_diffuse.png"),
+                directory.join("shot_emissive.png")
+            ));
+        assert!(paths.iter().all(|path| path.exists()));
+    }
+
+    #[test]
+    fn sequences_are_numbered_in_order()
+    {
+        let directory = test_directory("sequence");
+        let mut sequence = FrameSequence::new(&directory, "frame").unwrap();
+
+        for _ in 0..3
+        {
+            sequence.record(&RgbaImage::new(1, 1)).unwrap();
+        }
+
+        assert_eq!(sequence.frame_count(), 3);
+        assert!(directory.join("frame_00002.png").exists());
+        assert_eq!(sequence_frame_path(&directory, "frame", 12), directory.join("frame_00012.png"));
+    }
+}
@@ -0,0 +1,325 @@
+/*!
+  Movement of characters that walk, jump and climb ladders in the grid.
+*/
+extern crate nalgebra as na;
+
+use grid::{BlockLookup, BLOCK_SIZE};
+use collision::{self, Aabb, Contacts};
+
+
+/// Distance below which a character counts as standing on the top of a ladder
+const EPSILON: f32 = 0.001;
+
+
+/**
+  Speeds are in world pixels per second and accelerations in pixels per
+  second squared
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ControllerParameters {
+    pub gravity: f32,
+    pub max_fall_speed: f32,
+    pub walk_speed: f32,
+    pub jump_speed: f32,
+    pub climb_speed: f32
+}
+
+impl Default for ControllerParameters {
+    fn default() -> Self {
+        Self {
+            gravity: 1200.,
+            max_fall_speed: 800.,
+            walk_speed: 150.,
+            jump_speed: 400.,
+            climb_speed: 100.
+        }
+    }
+}
+
+/**
+  The movement requested by the player or AI for a single update.
+  `horizontal` and `vertical` are between -1 and 1 and follow the world
+  axes, so a negative `vertical` means up
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CharacterInput {
+    pub horizontal: f32,
+    pub vertical: f32,
+    pub jump: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MovementMode {
+    Walking,
+    /// Attached to the ladder in the specified column of the grid
+    Climbing { column: i32 }
+}
+
+pub struct Character {
+    pub aabb: Aabb,
+    pub velocity: na::Vector2<f32>,
+    pub mode: MovementMode,
+    pub contacts: Contacts,
+    pub parameters: ControllerParameters
+}
+
+impl Character {
+    pub fn new(aabb: Aabb, parameters: ControllerParameters) -> Self {
+        Self {
+            aabb,
+            velocity: na::zero(),
+            mode: MovementMode::Walking,
+            contacts: Contacts::default(),
+            parameters
+        }
+    }
+
+    pub fn update<L: BlockLookup>(&mut self, grid: &L, input: &CharacterInput, delta_time: f32) {
+        if delta_time <= 0. {
+            return;
+        }
+
+        if self.mode == MovementMode::Walking && input.vertical != 0. {
+            if let Some(column) = self.ladder_to_attach_to(grid, input.vertical) {
+                self.mode = MovementMode::Climbing { column };
+                self.velocity = na::zero();
+            }
+        }
+
+        match self.mode {
+            MovementMode::Walking => self.walk(grid, input, delta_time),
+            MovementMode::Climbing{column} => self.climb(grid, input, column, delta_time)
+        }
+    }
+
+    fn walk<L: BlockLookup>(&mut self, grid: &L, input: &CharacterInput, delta_time: f32) {
+        let parameters = self.parameters;
+
+        self.velocity.x = input.horizontal * parameters.walk_speed;
+        self.velocity.y = (self.velocity.y + parameters.gravity * delta_time)
+            .min(parameters.max_fall_speed);
+
+        if input.jump && self.contacts.grounded {
+            self.velocity.y = -parameters.jump_speed;
+        }
+
+        let result = collision::move_and_collide(grid, &self.aabb, self.velocity, delta_time);
+        self.aabb = result.aabb;
+        self.velocity = result.velocity;
+        self.contacts = result.contacts;
+    }
+
+    fn climb<L: BlockLookup>(&mut self, grid: &L, input: &CharacterInput, column: i32, delta_time: f32) {
+        let parameters = self.parameters;
+
+        if input.jump {
+            self.mode = MovementMode::Walking;
+            self.velocity = na::Vector2::new(
+                    input.horizontal * parameters.walk_speed,
+                    -parameters.jump_speed
+                );
+            self.walk(grid, &CharacterInput{jump: false, .. *input}, delta_time);
+            return;
+        }
+
+        // Snap to the center of the ladder
+        self.aabb.min.x = column as f32 * BLOCK_SIZE + (BLOCK_SIZE - self.aabb.size.x) / 2.;
+
+        let ladder_top = match self.ladder_top(grid, column) {
+            Some(top) => top,
+            None => {
+                self.mode = MovementMode::Walking;
+                return;
+            }
+        };
+
+        // Climbing up stops with the feet on top of the ladder
+        let mut distance = input.vertical * parameters.climb_speed * delta_time;
+        distance = distance.max(ladder_top - self.aabb.max().y);
+
+        let result = collision::move_and_collide_through_platforms(
+                grid,
+                &self.aabb,
+                na::Vector2::new(0., distance),
+                1.
+            );
+        self.aabb = result.aabb;
+        self.velocity = na::zero();
+        self.contacts = result.contacts;
+
+        let at_top = self.aabb.max().y <= ladder_top + EPSILON;
+        let on_ground = result.contacts.grounded && input.vertical > 0.;
+        let off_ladder = self.ladder_top(grid, column).is_none();
+
+        if (at_top && input.vertical < 0.) || on_ground || off_ladder {
+            self.mode = MovementMode::Walking;
+            self.contacts = collision::contacts(grid, &self.aabb, true);
+        }
+    }
+
+    /**
+      Returns the column of a ladder at the center of the character. When
+      moving down, ladders right below the feet are included so that
+      characters can climb down from the top of a ladder
+    */
+    fn ladder_to_attach_to<L: BlockLookup>(&self, grid: &L, vertical: f32) -> Option<i32> {
+        let center = self.aabb.center();
+        let column = (center.x / BLOCK_SIZE).floor() as i32;
+
+        let probe = if vertical > 0. {
+            Aabb::new(self.aabb.min, self.aabb.size + na::Vector2::new(0., 1.))
+        }
+        else {
+            self.aabb
+        };
+
+        let on_ladder = probe.overlapping_cells()
+            .iter()
+            .any(|cell| cell.x == column && collision::is_climbable(grid, cell));
+
+        if on_ladder {
+            Some(column)
+        }
+        else {
+            None
+        }
+    }
+
+    /**
+      Returns the y coordinate of the top of the ladder that the character
+      overlaps in `column`, or None if there is no such ladder. A ladder right
+      below the feet counts as overlapping
+    */
+    fn ladder_top<L: BlockLookup>(&self, grid: &L, column: i32) -> Option<f32> {
+        let probe = Aabb::new(self.aabb.min, self.aabb.size + na::Vector2::new(0., 1.));
+
+        let rows = probe.overlapping_cells()
+            .into_iter()
+            .filter(|cell| cell.x == column && collision::is_climbable(grid, cell))
+            .map(|cell| cell.y)
+            .collect::<Vec<_>>();
+
+        rows.iter().min().map(|&row| {
+            let mut top = row;
+            while collision::is_climbable(grid, &na::Vector2::new(column, top - 1)) {
+                top -= 1;
+            }
+            top as f32 * BLOCK_SIZE
+        })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+    use grid::BlockType;
+    use std::collections::HashMap;
+
+    /*
+      A floor at row 3 with a ladder going through it down to the floor at
+      row 6
+
+         ###H###
+         ...H...
+         ...H...
+         #######
+    */
+    fn ladder_grid() -> HashMap<na::Vector2<i32>, BlockType> {
+        let mut grid = HashMap::new();
+        for x in -3..4 {
+            grid.insert(na::Vector2::new(x, 6), BlockType::Stone);
+            if x != 0 {
+                grid.insert(na::Vector2::new(x, 3), BlockType::Stone);
+            }
+        }
+        for y in 3..6 {
+            grid.insert(na::Vector2::new(0, y), BlockType::StoneLadder);
+        }
+        grid
+    }
+
+    fn character(x: f32, y: f32) -> Character {
+        Character::new(
+            Aabb::new(na::Vector2::new(x, y), na::Vector2::new(16., 24.)),
+            ControllerParameters::default()
+        )
+    }
+
+    fn run(character: &mut Character, grid: &HashMap<na::Vector2<i32>, BlockType>, input: CharacterInput, steps: usize) {
+        for _ in 0..steps {
+            character.update(grid, &input, 1. / 60.);
+        }
+    }
+
+    #[test]
+    fn characters_attach_and_snap_to_ladders()
+    {
+        let grid = ladder_grid();
+        let mut character = character(4., 168.);
+
+        run(&mut character, &grid, CharacterInput{vertical: -1., .. Default::default()}, 1);
+
+        assert_eq!(character.mode, MovementMode::Climbing{column: 0});
+        assert_eq!(character.aabb.min.x, 8.);
+        assert!(character.aabb.min.y < 168.);
+    }
+
+    #[test]
+    fn climbing_up_dismounts_onto_the_floor_above()
+    {
+        let grid = ladder_grid();
+        let mut character = character(8., 168.);
+
+        run(&mut character, &grid, CharacterInput{vertical: -1., .. Default::default()}, 120);
+
+        assert_eq!(character.mode, MovementMode::Walking);
+        assert!((character.aabb.max().y - 96.).abs() < 0.01);
+        assert!(character.contacts.grounded);
+
+        // Standing on the top of the ladder without falling through it
+        run(&mut character, &grid, CharacterInput::default(), 30);
+        assert!((character.aabb.max().y - 96.).abs() < 0.01);
+    }
+
+    #[test]
+    fn characters_can_climb_down_from_the_top()
+    {
+        let grid = ladder_grid();
+        let mut character = character(8., 72.);
+
+        run(&mut character, &grid, CharacterInput{vertical: 1., .. Default::default()}, 200);
+
+        assert_eq!(character.mode, MovementMode::Walking);
+        assert!((character.aabb.max().y - 192.).abs() < 0.01);
+    }
+
+    #[test]
+    fn ladders_do_not_block_walking()
+    {
+        let grid = ladder_grid();
+        let mut character = character(-64., 168.);
+
+        run(&mut character, &grid, CharacterInput{horizontal: 1., .. Default::default()}, 60);
+
+        assert_eq!(character.mode, MovementMode::Walking);
+        assert!(character.aabb.min.x > 64.);
+    }
+
+    #[test]
+    fn jumping_detaches_from_ladders()
+    {
+        let grid = ladder_grid();
+        let mut character = character(8., 150.);
+        character.mode = MovementMode::Climbing{column: 0};
+
+        run(&mut character, &grid, CharacterInput{jump: true, horizontal: 1., .. Default::default()}, 1);
+
+        assert_eq!(character.mode, MovementMode::Walking);
+        assert!(character.velocity.y < 0.);
+        assert!(character.velocity.x > 0.);
+    }
+}
@@ -0,0 +1,440 @@
+/*!
+  Collision between axis aligned boxes and the blocks of a grid.
+
+  Movement is resolved one axis at a time. For each axis every column or row
+  of cells between the start and end of the movement is checked, so bodies
+  can't pass through blocks no matter how fast they move.
+
+  The topmost block of a ladder acts as a one way platform: it stops things
+  moving down onto it but nothing else. Movement which should pass through
+  platforms, like climbing down a ladder, uses
+  `move_and_collide_through_platforms`.
+*/
+extern crate nalgebra as na;
+
+use grid::{BlockLookup, BLOCK_SIZE};
+
+
+/// Distance below which edges are considered to be touching
+const EPSILON: f32 = 0.001;
+/// Distance used when checking for blocks next to a box
+const PROBE_DISTANCE: f32 = 0.01;
+/// Maximum number of blocks a box is pushed out of per call to `depenetrate`
+const MAX_DEPENETRATION_STEPS: usize = 8;
+
+
+/**
+  Axis aligned box in world pixels
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: na::Vector2<f32>,
+    pub size: na::Vector2<f32>
+}
+
+impl Aabb {
+    pub fn new(min: na::Vector2<f32>, size: na::Vector2<f32>) -> Self {
+        Self { min, size }
+    }
+
+    /**
+      Returns the smallest box containing all `points`, which must not be
+      empty
+    */
+    pub fn around_points(points: &[na::Vector2<f32>]) -> Aabb {
+        let (mut min, mut max) = (points[0], points[0]);
+        for point in &points[1..] {
+            min = na::Vector2::new(min.x.min(point.x), min.y.min(point.y));
+            max = na::Vector2::new(max.x.max(point.x), max.y.max(point.y));
+        }
+        Aabb::new(min, max - min)
+    }
+
+    pub fn max(&self) -> na::Vector2<f32> {
+        self.min + self.size
+    }
+
+    pub fn center(&self) -> na::Vector2<f32> {
+        self.min + self.size * 0.5
+    }
+
+    pub fn translated(&self, offset: na::Vector2<f32>) -> Aabb {
+        Aabb::new(self.min + offset, self.size)
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        let (max, other_max) = (self.max(), other.max());
+
+        self.min.x < other_max.x && other.min.x < max.x
+            && self.min.y < other_max.y && other.min.y < max.y
+    }
+
+    /**
+      Returns the first and last cell along `axis` that the box overlaps.
+      Cells that are only touched by an edge are not included
+    */
+    fn cell_range(&self, axis: usize) -> (i32, i32) {
+        (
+            ((self.min[axis] + EPSILON) / BLOCK_SIZE).floor() as i32,
+            ((self.max()[axis] - EPSILON) / BLOCK_SIZE).floor() as i32
+        )
+    }
+
+    /**
+      Returns the first and last cell that the box overlaps, both inclusive
+    */
+    pub fn cell_bounds(&self) -> (na::Vector2<i32>, na::Vector2<i32>) {
+        let (min_x, max_x) = self.cell_range(0);
+        let (min_y, max_y) = self.cell_range(1);
+        (na::Vector2::new(min_x, min_y), na::Vector2::new(max_x, max_y))
+    }
+
+    /**
+      Returns all cells that the box overlaps
+    */
+    pub fn overlapping_cells(&self) -> Vec<na::Vector2<i32>> {
+        let (min, max) = self.cell_bounds();
+
+        let mut result = vec!();
+        for y in min.y..max.y + 1 {
+            for x in min.x..max.x + 1 {
+                result.push(na::Vector2::new(x, y));
+            }
+        }
+        result
+    }
+
+    /**
+      Returns the box that covers a single cell of the grid
+    */
+    pub fn for_cell(cell: &na::Vector2<i32>) -> Aabb {
+        Aabb::new(
+            na::Vector2::new(cell.x as f32, cell.y as f32) * BLOCK_SIZE,
+            na::Vector2::new(BLOCK_SIZE, BLOCK_SIZE)
+        )
+    }
+}
+
+
+/**
+  Sides of a box which are touching solid blocks
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Contacts {
+    /// There is a block right below the box
+    pub grounded: bool,
+    pub wall_left: bool,
+    pub wall_right: bool,
+    pub ceiling: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollisionResult {
+    pub aabb: Aabb,
+    /// The velocity with the components that were blocked set to 0
+    pub velocity: na::Vector2<f32>,
+    pub contacts: Contacts
+}
+
+
+pub fn is_solid<L: BlockLookup>(grid: &L, cell: &na::Vector2<i32>) -> bool {
+    grid.block_type(cell)
+        .map(|block_type| block_type.is_colliding())
+        .unwrap_or(false)
+}
+
+pub fn is_climbable<L: BlockLookup>(grid: &L, cell: &na::Vector2<i32>) -> bool {
+    grid.block_type(cell)
+        .map(|block_type| block_type.is_climbable())
+        .unwrap_or(false)
+}
+
+/**
+  Returns true if the cell is the top of a ladder
+*/
+pub fn is_platform<L: BlockLookup>(grid: &L, cell: &na::Vector2<i32>) -> bool {
+    is_climbable(grid, cell) && !is_climbable(grid, &(cell - na::Vector2::new(0, 1)))
+}
+
+pub fn overlaps_solid<L: BlockLookup>(grid: &L, aabb: &Aabb) -> bool {
+    aabb.overlapping_cells().iter().any(|cell| is_solid(grid, cell))
+}
+
+
+fn cell_on_axis(axis: usize, along: i32, across: i32) -> na::Vector2<i32> {
+    if axis == 0 {
+        na::Vector2::new(along, across)
+    }
+    else {
+        na::Vector2::new(across, along)
+    }
+}
+
+/**
+  Moves `aabb` by `distance` along `axis` until it hits a solid block.
+  Returns the distance that was actually moved and true if a block was hit.
+  Platforms block downward movement if `use_platforms` is set
+*/
+pub fn sweep<L: BlockLookup>(
+        grid: &L,
+        aabb: &Aabb,
+        axis: usize,
+        distance: f32,
+        use_platforms: bool
+    ) -> (f32, bool)
+{
+    if distance == 0. {
+        return (0., false);
+    }
+
+    // Only rows below the box are checked when moving down, so platforms
+    // are never hit from below or from the side
+    let platforms_block = use_platforms && axis == 1 && distance > 0.;
+
+    let (across_min, across_max) = aabb.cell_range(1 - axis);
+    let column_is_solid = |along| {
+        (across_min..across_max + 1).any(|across| {
+            let cell = cell_on_axis(axis, along, across);
+            is_solid(grid, &cell) || (platforms_block && is_platform(grid, &cell))
+        })
+    };
+
+    if distance > 0. {
+        let leading = aabb.max()[axis];
+        let first = ((leading - EPSILON) / BLOCK_SIZE).floor() as i32 + 1;
+        let last = ((leading + distance) / BLOCK_SIZE).ceil() as i32 - 1;
+
+        for along in first..last + 1 {
+            if column_is_solid(along) {
+                let allowed = along as f32 * BLOCK_SIZE - leading;
+                return (allowed.max(0.).min(distance), true);
+            }
+        }
+    }
+    else {
+        let leading = aabb.min[axis];
+        let first = ((leading + EPSILON) / BLOCK_SIZE).ceil() as i32 - 1;
+        let last = ((leading + distance) / BLOCK_SIZE).floor() as i32;
+
+        let mut along = first;
+        while along >= last {
+            if column_is_solid(along) {
+                let allowed = (along + 1) as f32 * BLOCK_SIZE - leading;
+                return (allowed.min(0.).max(distance), true);
+            }
+            along -= 1;
+        }
+    }
+
+    (distance, false)
+}
+
+/**
+  Pushes the box out of any solid blocks it overlaps along the axis with
+  the smallest overlap
+*/
+pub fn depenetrate<L: BlockLookup>(grid: &L, aabb: &Aabb) -> Aabb {
+    let mut result = *aabb;
+
+    for _ in 0..MAX_DEPENETRATION_STEPS {
+        let cell = match result.overlapping_cells().into_iter().find(|cell| is_solid(grid, cell)) {
+            Some(cell) => cell,
+            None => break
+        };
+
+        let block = Aabb::for_cell(&cell);
+        let (max, block_max) = (result.max(), block.max());
+
+        let overlap = na::Vector2::new(
+                max.x.min(block_max.x) - result.min.x.max(block.min.x),
+                max.y.min(block_max.y) - result.min.y.max(block.min.y)
+            );
+
+        let axis = if overlap.x < overlap.y {0} else {1};
+        let direction = if result.center()[axis] < block.center()[axis] {-1.} else {1.};
+
+        let mut offset = na::Vector2::new(0., 0.);
+        offset[axis] = overlap[axis] * direction;
+        result = result.translated(offset);
+    }
+
+    result
+}
+
+pub fn contacts<L: BlockLookup>(grid: &L, aabb: &Aabb, use_platforms: bool) -> Contacts {
+    Contacts {
+        grounded: sweep(grid, aabb, 1, PROBE_DISTANCE, use_platforms).1,
+        ceiling: sweep(grid, aabb, 1, -PROBE_DISTANCE, use_platforms).1,
+        wall_left: sweep(grid, aabb, 0, -PROBE_DISTANCE, use_platforms).1,
+        wall_right: sweep(grid, aabb, 0, PROBE_DISTANCE, use_platforms).1,
+    }
+}
+
+/**
+  Moves `aabb` with `velocity` for `delta_time` seconds and resolves
+  collisions with the solid blocks of the grid. The box is first pushed out
+  of any blocks it already overlaps, then moved horizontally and finally
+  vertically.
+*/
+pub fn move_and_collide<L: BlockLookup>(
+        grid: &L,
+        aabb: &Aabb,
+        velocity: na::Vector2<f32>,
+        delta_time: f32
+    ) -> CollisionResult
+{
+    move_internal(grid, aabb, velocity, delta_time, true)
+}
+
+/**
+  Like `move_and_collide` but platforms don't stop the box
+*/
+pub fn move_and_collide_through_platforms<L: BlockLookup>(
+        grid: &L,
+        aabb: &Aabb,
+        velocity: na::Vector2<f32>,
+        delta_time: f32
+    ) -> CollisionResult
+{
+    move_internal(grid, aabb, velocity, delta_time, false)
+}
+
+fn move_internal<L: BlockLookup>(
+        grid: &L,
+        aabb: &Aabb,
+        velocity: na::Vector2<f32>,
+        delta_time: f32,
+        use_platforms: bool
+    ) -> CollisionResult
+{
+    let mut result = depenetrate(grid, aabb);
+    let mut velocity = velocity;
+
+    for axis in 0..2 {
+        let (moved, hit) = sweep(grid, &result, axis, velocity[axis] * delta_time, use_platforms);
+
+        let mut offset = na::Vector2::new(0., 0.);
+        offset[axis] = moved;
+        result = result.translated(offset);
+
+        if hit {
+            velocity[axis] = 0.;
+        }
+    }
+
+    CollisionResult {
+        aabb: result,
+        velocity,
+        contacts: contacts(grid, &result, use_platforms)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+    use grid::BlockType;
+    use std::collections::HashMap;
+
+    fn floor_grid() -> HashMap<na::Vector2<i32>, BlockType> {
+        let mut grid = HashMap::new();
+        for x in -5..5 {
+            grid.insert(na::Vector2::new(x, 2), BlockType::Stone);
+        }
+        grid.insert(na::Vector2::new(3, 1), BlockType::Stone);
+        grid
+    }
+
+    fn body(x: f32, y: f32) -> Aabb {
+        Aabb::new(na::Vector2::new(x, y), na::Vector2::new(16., 24.))
+    }
+
+    #[test]
+    fn falling_bodies_land_on_the_ground()
+    {
+        let result = move_and_collide(&floor_grid(), &body(0., 0.), na::Vector2::new(0., 200.), 1.);
+
+        assert_eq!(result.aabb.max().y, 64.);
+        assert_eq!(result.velocity, na::Vector2::new(0., 0.));
+        assert!(result.contacts.grounded);
+        assert!(!result.contacts.ceiling);
+    }
+
+    #[test]
+    fn fast_bodies_do_not_tunnel()
+    {
+        let mut grid = HashMap::new();
+        grid.insert(na::Vector2::new(100, 0), BlockType::Stone);
+
+        let result = move_and_collide(&grid, &body(0., 0.), na::Vector2::new(100000., 0.), 1.);
+
+        assert_eq!(result.aabb.max().x, 3200.);
+        assert!(result.contacts.wall_right);
+        assert!(!result.contacts.wall_left);
+    }
+
+    #[test]
+    fn walls_stop_horizontal_movement_only()
+    {
+        let result = move_and_collide(&floor_grid(), &body(64., 40.), na::Vector2::new(-50., 10.), 1.);
+
+        // The block at (3, 1) is to the right so nothing stops leftward movement
+        assert_eq!(result.aabb.min.x, 14.);
+
+        let result = move_and_collide(&floor_grid(), &body(64., 40.), na::Vector2::new(50., 0.), 1.);
+
+        assert_eq!(result.aabb.max().x, 96.);
+        assert_eq!(result.velocity.x, 0.);
+        assert!(result.contacts.wall_right);
+        assert!(result.contacts.grounded);
+    }
+
+    #[test]
+    fn bodies_touching_only_an_edge_are_not_blocked()
+    {
+        // Exactly on top of the floor, moving along it
+        let result = move_and_collide(&floor_grid(), &body(-64., 40.), na::Vector2::new(32., 0.), 1.);
+
+        assert_eq!(result.aabb.min, na::Vector2::new(-32., 40.));
+        assert!(result.contacts.grounded);
+    }
+
+    #[test]
+    fn ladders_are_only_solid_from_above()
+    {
+        let mut grid = floor_grid();
+        grid.insert(na::Vector2::new(1, 0), BlockType::StoneLadder);
+        grid.insert(na::Vector2::new(1, 1), BlockType::StoneLadder);
+
+        // Walking through the ladder
+        let result = move_and_collide(&grid, &body(0., 40.), na::Vector2::new(40., 0.), 1.);
+        assert_eq!(result.aabb.min.x, 40.);
+
+        // Landing on top of it
+        let result = move_and_collide(&grid, &body(36., -64.), na::Vector2::new(0., 100.), 1.);
+        assert_eq!(result.aabb.max().y, 0.);
+        assert!(result.contacts.grounded);
+
+        // Climbing down through it
+        let result = move_and_collide_through_platforms(
+                &grid,
+                &body(36., -24.),
+                na::Vector2::new(0., 20.),
+                1.
+            );
+        assert_eq!(result.aabb.max().y, 20.);
+        assert!(!result.contacts.grounded);
+    }
+
+    #[test]
+    fn overlapping_bodies_are_pushed_out()
+    {
+        let result = depenetrate(&floor_grid(), &body(0., 45.));
+
+        assert_eq!(result.max().y, 64.);
+        assert!(!overlaps_solid(&floor_grid(), &result));
+    }
+}
@@ -5,8 +5,10 @@ pub const DEFAULT_VERTEX_SHADER: &'static str = r#"
         in vec2 tex_coords;
         out vec2 v_tex_coords;
         uniform mat4 matrix;
+        // Position and size of the sprite's part of the texture
+        uniform vec4 uv_rect;
         void main() {
-            v_tex_coords = tex_coords;
+            v_tex_coords = uv_rect.xy + tex_coords * uv_rect.zw;
             gl_Position = matrix * vec4(position, 0.0, 1.0);
         }
     "#;
@@ -15,7 +17,35 @@ pub const DEFAULT_FRAGMENT_SHADER: &'static str = r#"
         in vec2 v_tex_coords;
         out vec4 color;
         uniform sampler2D tex;
+        uniform vec4 tint;
         void main() {
-            color = texture(tex, v_tex_coords);
+            color = texture(tex, v_tex_coords) * tint;
+        }
+    "#;
+pub const INSTANCED_VERTEX_SHADER: &'static str = r#"
+        #version 140
+        in vec2 position;
+        in vec2 tex_coords;
+        in mat4 matrix;
+        in vec4 uv_rect;
+        in vec4 tint;
+        in float depth;
+        out vec2 v_tex_coords;
+        out vec4 v_tint;
+        void main() {
+            v_tex_coords = uv_rect.xy + tex_coords * uv_rect.zw;
+            v_tint = tint;
+            gl_Position = matrix * vec4(position, 0.0, 1.0);
+            gl_Position.z = depth;
+        }
+    "#;
+pub const INSTANCED_FRAGMENT_SHADER: &'static str = r#"
+        #version 140
+        in vec2 v_tex_coords;
+        in vec4 v_tint;
+        out vec4 color;
+        uniform sampler2D tex;
+        void main() {
+            color = texture(tex, v_tex_coords) * v_tint;
         }
     "#;
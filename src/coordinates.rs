@@ -0,0 +1,358 @@
+/*!
+  Conversion between the coordinate systems used when drawing.
+
+  - Window coordinates are the logical pixels that the window system reports
+    cursor positions in, starting at the top left corner of the window.
+  - Framebuffer coordinates are physical pixels of the framebuffer. On HiDPI
+    screens there are `hidpi_factor` framebuffer pixels per window pixel.
+  - Clip coordinates go from -1 to 1 across the framebuffer with y pointing
+    up, as in OpenGL.
+  - World coordinates are the pixels that sprites and blocks are positioned
+    in. They are turned into clip coordinates by the camera and the window
+    scaling matrix.
+  - Cell coordinates are the cells of the `Grid`.
+*/
+extern crate nalgebra as na;
+
+use glium::Display;
+
+use camera_state::CameraState;
+use collision::Aabb;
+use drawing_util;
+use grid;
+
+
+/**
+  The size of what is drawn to and how it relates to the window
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport
+{
+    pub framebuffer_size: (u32, u32),
+    /// Framebuffer pixels per window pixel
+    pub hidpi_factor: f32,
+}
+
+impl Viewport
+{
+    pub fn new(framebuffer_size: (u32, u32), hidpi_factor: f32) -> Viewport
+    {
+        Viewport {
+            framebuffer_size: framebuffer_size,
+            hidpi_factor: hidpi_factor,
+        }
+    }
+
+    pub fn from_display(display: &Display) -> Viewport
+    {
+        Viewport::new(display.get_framebuffer_dimensions(), display.gl_window().hidpi_factor())
+    }
+}
+
+
+/**
+  The matrix which moves world coordinates to clip coordinates when drawing
+  to a target of `target_size`
+*/
+pub fn world_to_clip_matrix(camera_state: &CameraState, target_size: (u32, u32)) -> na::Matrix4<f32>
+{
+    camera_state.get_matrix(target_size)
+        * drawing_util::get_window_scaling_matrix((target_size.0 as f32, target_size.1 as f32))
+}
+
+pub fn world_to_clip(camera_state: &CameraState, target_size: (u32, u32), world: na::Vector2<f32>)
+    -> na::Vector2<f32>
+{
+    let clip = world_to_clip_matrix(camera_state, target_size) * na::Vector4::new(world.x, world.y, 0., 1.);
+    na::Vector2::new(clip.x, clip.y)
+}
+
+/**
+  The world position drawn at `clip`. The camera matrix can always be
+  inverted as long as the zoom is not 0
+*/
+pub fn clip_to_world(camera_state: &CameraState, target_size: (u32, u32), clip: na::Vector2<f32>)
+    -> na::Vector2<f32>
+{
+    let inverse = world_to_clip_matrix(camera_state, target_size)
+        .try_inverse()
+        .expect("The camera matrix can not be inverted, is the zoom 0?");
+    let world = inverse * na::Vector4::new(clip.x, clip.y, 0., 1.);
+    na::Vector2::new(world.x, world.y)
+}
+
+/**
+  The smallest box of world pixels which contains everything that is visible
+  on a target of `target_size`. If the camera is rotated parts of the box are
+  outside the target
+*/
+pub fn visible_world_rect(camera_state: &CameraState, target_size: (u32, u32)) -> Aabb
+{
+    let corners = [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)].iter()
+        .map(|&(x, y)| clip_to_world(camera_state, target_size, na::Vector2::new(x, y)))
+        .collect::<Vec<_>>();
+
+    Aabb::around_points(&corners)
+}
+
+
+pub fn window_to_framebuffer(viewport: &Viewport, window: na::Vector2<f32>) -> na::Vector2<f32>
+{
+    window * viewport.hidpi_factor
+}
+
+pub fn framebuffer_to_window(viewport: &Viewport, framebuffer: na::Vector2<f32>) -> na::Vector2<f32>
+{
+    framebuffer / viewport.hidpi_factor
+}
+
+pub fn framebuffer_to_clip(viewport: &Viewport, framebuffer: na::Vector2<f32>) -> na::Vector2<f32>
+{
+    let (width, height) = (viewport.framebuffer_size.0 as f32, viewport.framebuffer_size.1 as f32);
+    na::Vector2::new(2. * framebuffer.x / width - 1., 1. - 2. * framebuffer.y / height)
+}
+
+pub fn clip_to_framebuffer(viewport: &Viewport, clip: na::Vector2<f32>) -> na::Vector2<f32>
+{
+    let (width, height) = (viewport.framebuffer_size.0 as f32, viewport.framebuffer_size.1 as f32);
+    na::Vector2::new((clip.x + 1.) * width / 2., (1. - clip.y) * height / 2.)
+}
+
+
+/**
+  The world position under a point of the window, for example the cursor
+*/
+pub fn window_to_world(camera_state: &CameraState, viewport: &Viewport, window: na::Vector2<f32>)
+    -> na::Vector2<f32>
+{
+    let clip = framebuffer_to_clip(viewport, window_to_framebuffer(viewport, window));
+    clip_to_world(camera_state, viewport.framebuffer_size, clip)
+}
+
+pub fn world_to_window(camera_state: &CameraState, viewport: &Viewport, world: na::Vector2<f32>)
+    -> na::Vector2<f32>
+{
+    let clip = world_to_clip(camera_state, viewport.framebuffer_size, world);
+    framebuffer_to_window(viewport, clip_to_framebuffer(viewport, clip))
+}
+
+/**
+  The grid cell under a point of the window
+*/
+pub fn window_to_cell(camera_state: &CameraState, viewport: &Viewport, window: na::Vector2<f32>)
+    -> na::Vector2<i32>
+{
+    grid::world_to_cell(window_to_world(camera_state, viewport, window))
+}
+
+/**
+  The position in the window of the top left corner of a grid cell
+*/
+pub fn cell_to_window(camera_state: &CameraState, viewport: &Viewport, cell: na::Vector2<i32>)
+    -> na::Vector2<f32>
+{
+    world_to_window(camera_state, viewport, grid::cell_to_world(cell))
+}
+
+
+/**
+  Changes the zoom of the camera while keeping the world position under a
+  point of the window, for example the cursor, in the same place
+*/
+pub fn zoom_around(camera_state: &mut CameraState, viewport: &Viewport, zoom: f32, window: na::Vector2<f32>)
+{
+    let anchor = window_to_world(camera_state, viewport, window);
+    camera_state.set_zoom(zoom);
+    let moved = window_to_world(camera_state, viewport, window);
+
+    // Moving the camera by the window scaling matrix applied to a distance
+    // in world pixels moves the world under every point of the screen by
+    // that distance, regardless of zoom and rotation
+    let (width, height) = (viewport.framebuffer_size.0 as f32, viewport.framebuffer_size.1 as f32);
+    let error = anchor - moved;
+    let position = camera_state.get_position();
+    camera_state.set_position(position + na::Vector2::new(2. * error.x / width, -2. * error.y / height));
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    const CASES: usize = 500;
+
+    /**
+      A generator which gives the same numbers for the same case every run,
+      so that a failing case can be reproduced
+    */
+    fn case_rng(case: usize) -> XorShiftRng
+    {
+        XorShiftRng::from_seed([0x5eed_2022, case as u32 + 1, 0x9e37_79b9, 0x7f4a_7c15])
+    }
+
+    fn assert_close(a: na::Vector2<f32>, b: na::Vector2<f32>, tolerance: f32, case: &str)
+    {
+        assert!(
+            (a.x - b.x).abs() < tolerance && (a.y - b.y).abs() < tolerance,
+            "{:?} != {:?} for {}", a, b, case
+        );
+    }
+
+    fn random_setup<R: Rng>(rng: &mut R) -> (CameraState, Viewport)
+    {
+        let mut camera_state = CameraState::new();
+        camera_state.set_position(na::Vector2::new(rng.gen_range(-5., 5.), rng.gen_range(-5., 5.)));
+        camera_state.set_zoom(rng.gen_range(0.25, 8.));
+        camera_state.set_angle(rng.gen_range(-3.2, 3.2));
+
+        let hidpi_factor = [1., 1.25, 1.5, 2., 3.][rng.gen_range(0, 5)];
+        let viewport = Viewport::new((rng.gen_range(64, 4096), rng.gen_range(64, 4096)), hidpi_factor);
+
+        (camera_state, viewport)
+    }
+
+    #[test]
+    fn window_and_world_coordinates_round_trip()
+    {
+        for case in 0..CASES
+        {
+            let mut rng = case_rng(case);
+            let (camera_state, viewport) = random_setup(&mut rng);
+            let window = na::Vector2::new(
+                rng.gen_range(0., viewport.framebuffer_size.0 as f32 / viewport.hidpi_factor),
+                rng.gen_range(0., viewport.framebuffer_size.1 as f32 / viewport.hidpi_factor)
+            );
+            let case = format!("case {}: {:?} at {:?}, zoom {}, angle {}, position {:?}",
+                case, viewport, window, camera_state.get_zoom(), camera_state.get_angle(), camera_state.get_position());
+
+            let world = window_to_world(&camera_state, &viewport, window);
+            assert_close(world_to_window(&camera_state, &viewport, world), window, 0.05, &case);
+        }
+    }
+
+    #[test]
+    fn cells_round_trip_through_the_window()
+    {
+        for case in 0..CASES
+        {
+            let mut rng = case_rng(case);
+            let (camera_state, viewport) = random_setup(&mut rng);
+            let cell = na::Vector2::new(rng.gen_range(-500, 500), rng.gen_range(-500, 500));
+            let case = format!("case {}: {:?} at {:?}, zoom {}, angle {}, position {:?}",
+                case, viewport, cell, camera_state.get_zoom(), camera_state.get_angle(), camera_state.get_position());
+
+            // The middle of the cell so that rounding does not move it to a
+            // neighbouring cell
+            let corner = cell_to_window(&camera_state, &viewport, cell);
+            let next_corner = cell_to_window(&camera_state, &viewport, cell + na::Vector2::new(1, 1));
+            let middle = (corner + next_corner) / 2.;
+
+            assert_eq!(window_to_cell(&camera_state, &viewport, middle), cell, "{}", case);
+        }
+    }
+
+    #[test]
+    fn unmoved_camera_draws_world_pixels_as_framebuffer_pixels()
+    {
+        let viewport = Viewport::new((800, 600), 2.);
+        let camera_state = CameraState::new();
+
+        // The window scaling matrix puts the world origin a quarter of the
+        // way into the framebuffer
+        let origin = world_to_window(&camera_state, &viewport, na::zero());
+        assert_close(origin, na::Vector2::new(100., 75.), 0.001, "origin");
+
+        let right = world_to_window(&camera_state, &viewport, na::Vector2::new(10., 0.));
+        assert_close(right - origin, na::Vector2::new(5., 0.), 0.001, "one world pixel");
+    }
+
+    #[test]
+    fn zooming_scales_around_the_middle_of_the_screen()
+    {
+        let viewport = Viewport::new((800, 600), 1.);
+        let mut camera_state = CameraState::new();
+        let middle = window_to_world(&camera_state, &viewport, na::Vector2::new(400., 300.));
+
+        camera_state.set_zoom(4.);
+
+        assert_close(
+            window_to_world(&camera_state, &viewport, na::Vector2::new(400., 300.)),
+            middle,
+            0.001,
+            "middle"
+        );
+        assert_close(
+            window_to_world(&camera_state, &viewport, na::Vector2::new(440., 300.)) - middle,
+            na::Vector2::new(10., 0.),
+            0.001,
+            "zoomed pixel"
+        );
+    }
+
+    #[test]
+    fn zooming_around_a_point_keeps_it_in_place()
+    {
+        for case in 0..CASES
+        {
+            let mut rng = case_rng(case);
+            let (mut camera_state, viewport) = random_setup(&mut rng);
+            let window = na::Vector2::new(
+                rng.gen_range(0., viewport.framebuffer_size.0 as f32 / viewport.hidpi_factor),
+                rng.gen_range(0., viewport.framebuffer_size.1 as f32 / viewport.hidpi_factor)
+            );
+            let before = window_to_world(&camera_state, &viewport, window);
+
+            let zoom = rng.gen_range(0.25, 8.);
+            zoom_around(&mut camera_state, &viewport, zoom, window);
+
+            let case = format!("case {}: {:?} at {:?}, zoom {}", case, viewport, window, zoom);
+            assert_eq!(camera_state.get_zoom(), zoom);
+            assert_close(world_to_window(&camera_state, &viewport, before), window, 0.05, &case);
+        }
+    }
+
+    #[test]
+    fn rotation_keeps_distances_on_non_square_targets()
+    {
+        let viewport = Viewport::new((800, 400), 1.);
+        let mut camera_state = CameraState::new();
+        camera_state.set_angle(::std::f32::consts::PI / 2.);
+
+        let origin = world_to_window(&camera_state, &viewport, na::zero());
+        let right = world_to_window(&camera_state, &viewport, na::Vector2::new(10., 0.));
+
+        // A counter clockwise camera turns the world clockwise on the
+        // screen, which moves world x towards the bottom of the window
+        assert_close(right - origin, na::Vector2::new(0., 10.), 0.001, "rotated x axis");
+    }
+
+    #[test]
+    fn visible_rect_contains_the_whole_window()
+    {
+        for case in 0..CASES
+        {
+            let mut rng = case_rng(case);
+            let (camera_state, viewport) = random_setup(&mut rng);
+            let rect = visible_world_rect(&camera_state, viewport.framebuffer_size);
+
+            let (width, height) = (
+                viewport.framebuffer_size.0 as f32 / viewport.hidpi_factor,
+                viewport.framebuffer_size.1 as f32 / viewport.hidpi_factor
+            );
+            for &(x, y) in &[(0., 0.), (width, 0.), (0., height), (width, height), (width / 2., height / 3.)]
+            {
+                let world = window_to_world(&camera_state, &viewport, na::Vector2::new(x, y));
+                let (min, max) = (rect.min, rect.max());
+                let margin = 0.01 * (max.x - min.x).max(max.y - min.y);
+                assert!(
+                    world.x > min.x - margin && world.x < max.x + margin
+                        && world.y > min.y - margin && world.y < max.y + margin,
+                    "case {}: {:?} is outside {:?}", case, world, rect
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+/*!
+  Skipping things which are outside the view of the camera.
+
+  The view is the box of world pixels returned by
+  `coordinates::visible_world_rect`. Sprites are kept if the box around them,
+  which includes their rotation, intersects the view. Blocks are found by
+  looking up the cells overlapping the view instead of going through every
+  block of the grid.
+*/
+extern crate nalgebra as na;
+
+use collision::Aabb;
+use grid::{BlockLookup, BlockType, Block, Grid};
+use sprite::Sprite;
+
+use std::ops::AddAssign;
+
+
+/**
+  How many objects were drawn and skipped, for profiling
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats
+{
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+impl CullingStats
+{
+    pub fn new() -> CullingStats
+    {
+        CullingStats::default()
+    }
+
+    pub fn total(&self) -> usize
+    {
+        self.drawn + self.culled
+    }
+
+    pub fn reset(&mut self)
+    {
+        *self = CullingStats::default();
+    }
+}
+
+impl AddAssign for CullingStats
+{
+    fn add_assign(&mut self, other: CullingStats)
+    {
+        self.drawn += other.drawn;
+        self.culled += other.culled;
+    }
+}
+
+
+/**
+  Returns the items whose `bounds` intersect `view` and counts the rest as
+  culled in `stats`
+*/
+pub fn cull<'a, T, I, F>(items: I, view: &Aabb, bounds: F, stats: &mut CullingStats) -> Vec<&'a T>
+    where I: IntoIterator<Item=&'a T>,
+          F: Fn(&T) -> Aabb
+{
+    let mut visible = vec!();
+    for item in items
+    {
+        if bounds(item).intersects(view)
+        {
+            visible.push(item);
+        }
+        else
+        {
+            stats.culled += 1;
+        }
+    }
+
+    stats.drawn += visible.len();
+    visible
+}
+
+pub fn visible_sprites<'a, I>(sprites: I, view: &Aabb, stats: &mut CullingStats) -> Vec<&'a Sprite>
+    where I: IntoIterator<Item=&'a Sprite>
+{
+    cull(sprites, view, Sprite::world_bounds, stats)
+}
+
+/**
+  Returns the blocks of `grid` in view. Every block outside the view is
+  counted as culled
+*/
+pub fn visible_blocks<'a>(grid: &'a Grid, view: &Aabb, stats: &mut CullingStats)
+    -> Vec<(na::Vector2<i32>, &'a Block)>
+{
+    let visible = grid.blocks_in_view(view);
+
+    stats.drawn += visible.len();
+    stats.culled += grid.len() - visible.len();
+    visible
+}
+
+/**
+  Returns the cells in view which contain a block, for grids without
+  rendering data
+*/
+pub fn cells_in_view<L: BlockLookup>(grid: &L, view: &Aabb) -> Vec<(na::Vector2<i32>, BlockType)>
+{
+    view.overlapping_cells()
+        .into_iter()
+        .filter_map(|cell| grid.block_type(&cell).map(|block_type| (cell, block_type)))
+        .collect()
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use grid::BLOCK_SIZE;
+    use std::collections::HashMap;
+
+    #[test]
+    fn only_boxes_intersecting_the_view_are_kept()
+    {
+        let view = Aabb::new(na::Vector2::new(0., 0.), na::Vector2::new(100., 100.));
+        let boxes = vec!(
+            Aabb::new(na::Vector2::new(10., 10.), na::Vector2::new(5., 5.)),
+            Aabb::new(na::Vector2::new(-20., 50.), na::Vector2::new(30., 5.)),
+            Aabb::new(na::Vector2::new(150., 10.), na::Vector2::new(5., 5.)),
+            Aabb::new(na::Vector2::new(50., -40.), na::Vector2::new(5., 5.)),
+        );
+
+        let mut stats = CullingStats::new();
+        let visible = cull(&boxes, &view, |aabb| *aabb, &mut stats);
+
+        assert_eq!(visible, vec!(&boxes[0], &boxes[1]));
+        assert_eq!(stats, CullingStats{drawn: 2, culled: 2});
+        assert_eq!(stats.total(), boxes.len());
+    }
+
+    #[test]
+    fn only_cells_in_view_are_returned()
+    {
+        let mut grid = HashMap::new();
+        for x in -50..50
+        {
+            grid.insert(na::Vector2::new(x, 0), BlockType::Stone);
+        }
+        grid.insert(na::Vector2::new(2, 1), BlockType::StoneLadder);
+
+        let view = Aabb::new(
+            na::Vector2::new(BLOCK_SIZE * 1.5, -BLOCK_SIZE),
+            na::Vector2::new(BLOCK_SIZE * 2., BLOCK_SIZE * 2.5)
+        );
+        let mut cells = cells_in_view(&grid, &view);
+        cells.sort_by_key(|&(cell, _)| (cell.y, cell.x));
+
+        assert_eq!(cells, vec!(
+            (na::Vector2::new(1, 0), BlockType::Stone),
+            (na::Vector2::new(2, 0), BlockType::Stone),
+            (na::Vector2::new(3, 0), BlockType::Stone),
+            (na::Vector2::new(2, 1), BlockType::StoneLadder),
+        ));
+    }
+}
@@ -1,5 +1,11 @@
 extern crate nalgebra as na;
 
+use glium::texture::RawImage2d;
+use glium::texture::texture2d::Texture2d;
+use image::{self, imageops, ImageResult, RgbaImage};
+
+use std::path::Path;
+
 
 pub fn get_window_scaling_matrix(window_size: (f32, f32)) -> na::Matrix4<f32>
 {
@@ -22,3 +28,36 @@ pub fn get_window_scaling_matrix(window_size: (f32, f32)) -> na::Matrix4<f32>
         )
 }
 
+
+/**
+  Loads the image at `path` as pixels that can be uploaded to a texture
+*/
+pub fn try_load_texture<'a>(path: &Path) -> ImageResult<RawImage2d<'a, u8>>
+{
+    let image = image::open(path)?.to_rgba();
+    let dimensions = image.dimensions();
+
+    Ok(RawImage2d::from_raw_rgba(image.into_raw(), dimensions))
+}
+
+
+/**
+  Reads the contents of `texture` back from the GPU. The rows are flipped so
+  that the top of the image is the top of the rendered scene
+*/
+pub fn texture_to_image(texture: &Texture2d) -> RgbaImage
+{
+    raw_to_image(texture.read())
+}
+
+/**
+  Converts pixels read from OpenGL, which start at the bottom row, to an image
+  starting at the top row
+*/
+pub fn raw_to_image(raw: RawImage2d<u8>) -> RgbaImage
+{
+    let image = RgbaImage::from_raw(raw.width, raw.height, raw.data.into_owned())
+        .expect("Texture data does not match its size");
+
+    imageops::flip_vertical(&image)
+}
@@ -1,20 +1,19 @@
 extern crate nalgebra as na;
 
-use sprite::{SpriteFactory, Sprite};
-
 use std::collections::HashMap;
 
-use rand::{thread_rng, Rng};
-
-use glium;
-
-use std::sync::Arc;
+use level::{self, LevelError};
+use prefab::{self, Prefab, ConflictPolicy, PrefabConflict};
+use layer::Layer;
+use collision::Aabb;
 
 
-const BLOCK_SIZE: f32 = 32.;
+pub const BLOCK_SIZE: f32 = 32.;
+/// The number of cells along each side of a chunk
+pub const CHUNK_SIZE: i32 = 32;
 
 
-#[derive(Hash, PartialEq, Eq, Clone)]
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum BlockType {
     Stone,
     StoneLadder
@@ -22,8 +21,27 @@ pub enum BlockType {
 
 
 impl BlockType {
+    pub fn all() -> Vec<BlockType> {
+        vec!(BlockType::Stone, BlockType::StoneLadder)
+    }
+
+    /// The name used to refer to the block type in level files
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BlockType::Stone => "Stone",
+            BlockType::StoneLadder => "StoneLadder"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BlockType> {
+        BlockType::all().into_iter().find(|t| t.name() == name)
+    }
+
+    /// Colliding blocks are solid from all sides. Climbable blocks are not
+    /// colliding, but the top of a ladder can be stood on, see `collision`
     pub fn is_colliding(&self) -> bool {
         match *self {
+            BlockType::StoneLadder => false,
             _ => true
         }
     }
@@ -36,51 +54,470 @@ impl BlockType {
     }
 }
 
-fn sprite_for_block(
-        block_type: &BlockType,
-        position: na::Vector2<i32>,
-        sprite_factory: &SpriteFactory,
-        textures: &HashMap<BlockType, Vec<Arc<glium::texture::SrgbTexture2d>>>
-    ) -> Sprite
+/**
+  Returns the cell which contains a position in world pixels
+*/
+pub fn world_to_cell(position: na::Vector2<f32>) -> na::Vector2<i32> {
+    na::Vector2::new(
+        (position.x / BLOCK_SIZE).floor() as i32,
+        (position.y / BLOCK_SIZE).floor() as i32
+    )
+}
+
+/**
+  Returns the top left corner of a cell in world pixels
+*/
+pub fn cell_to_world(cell: na::Vector2<i32>) -> na::Vector2<f32> {
+    na::convert::<_, na::Vector2<f32>>(cell) * BLOCK_SIZE
+}
+
+
+/**
+  Returns the smallest and largest of a set of cells, both inclusive, or
+  None if there are no cells
+*/
+pub fn cell_bounds<'a, I>(cells: I) -> Option<(na::Vector2<i32>, na::Vector2<i32>)>
+    where I: IntoIterator<Item=&'a na::Vector2<i32>>
 {
-    let mut rng = thread_rng();
-    let texture = rng.choose(textures.get(block_type).unwrap()).unwrap();
+    cells.into_iter().fold(None, |bounds, pos| {
+        match bounds {
+            Some((min, max)) => Some((
+                na::Vector2::new(pos.x.min(min.x), pos.y.min(min.y)),
+                na::Vector2::new(pos.x.max(max.x), pos.y.max(max.y))
+            )),
+            None => Some((*pos, *pos))
+        }
+    })
+}
+
 
-    let mut sprite = sprite_factory.create_sprite(texture.clone());
-    sprite.set_position(na::convert::<_, na::Vector2<f32>>(position) * BLOCK_SIZE);
-    sprite
+/**
+  Read only access to the block types in a grid. This is implemented for
+  plain maps of block types as well so that code which only cares about the
+  layout of the level can be used without any rendering data.
+*/
+pub trait BlockLookup {
+    fn block_type(&self, position: &na::Vector2<i32>) -> Option<BlockType>;
+}
+
+impl BlockLookup for HashMap<na::Vector2<i32>, BlockType> {
+    fn block_type(&self, position: &na::Vector2<i32>) -> Option<BlockType> {
+        self.get(position).cloned()
+    }
 }
 
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Block {
     pub block_type: BlockType,
-    pub sprite: Sprite
+    /// The layer that the block is drawn on
+    pub layer: Layer
+}
+
+/**
+  Returns the chunk which contains a cell
+*/
+pub fn chunk_of(cell: &na::Vector2<i32>) -> na::Vector2<i32> {
+    na::Vector2::new(div_floor(cell.x, CHUNK_SIZE), div_floor(cell.y, CHUNK_SIZE))
+}
+
+/**
+  Returns the first cell of a chunk
+*/
+pub fn chunk_origin(chunk: &na::Vector2<i32>) -> na::Vector2<i32> {
+    *chunk * CHUNK_SIZE
+}
+
+fn div_floor(value: i32, divisor: i32) -> i32 {
+    let quotient = value / divisor;
+    if value % divisor < 0 { quotient - 1 } else { quotient }
+}
+
+fn index_in_chunk(cell: &na::Vector2<i32>) -> usize {
+    let local = *cell - chunk_origin(&chunk_of(cell));
+    (local.y * CHUNK_SIZE + local.x) as usize
+}
+
+
+/**
+  A square of `CHUNK_SIZE` by `CHUNK_SIZE` cells. The revision changes every
+  time the chunk changes so that data derived from it, like vertex buffers,
+  knows when to be rebuilt. Revisions come from a counter in the grid so a
+  chunk which is removed and added again never reuses an old revision
+*/
+pub struct Chunk {
+    position: na::Vector2<i32>,
+    cells: Vec<Option<Block>>,
+    block_count: usize,
+    revision: u64
 }
 
+impl Chunk {
+    fn new(position: na::Vector2<i32>) -> Self {
+        Self {
+            position,
+            cells: vec!(None; (CHUNK_SIZE * CHUNK_SIZE) as usize),
+            block_count: 0,
+            revision: 0
+        }
+    }
+
+    pub fn position(&self) -> na::Vector2<i32> {
+        self.position
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn len(&self) -> usize {
+        self.block_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.block_count == 0
+    }
+
+    /**
+      Returns the first and last cell of the chunk, both inclusive
+    */
+    pub fn cell_bounds(&self) -> (na::Vector2<i32>, na::Vector2<i32>) {
+        let min = chunk_origin(&self.position);
+        (min, min + na::Vector2::new(CHUNK_SIZE - 1, CHUNK_SIZE - 1))
+    }
+
+    /**
+      Returns every block in the chunk along with its cell
+    */
+    pub fn blocks(&self) -> Vec<(na::Vector2<i32>, &Block)> {
+        let origin = chunk_origin(&self.position);
+        self.cells.iter()
+            .enumerate()
+            .filter_map(|(index, block)| {
+                let index = index as i32;
+                block.as_ref().map(|block| {
+                    (origin + na::Vector2::new(index % CHUNK_SIZE, index / CHUNK_SIZE), block)
+                })
+            })
+            .collect()
+    }
+
+    fn get(&self, cell: &na::Vector2<i32>) -> Option<&Block> {
+        self.cells[index_in_chunk(cell)].as_ref()
+    }
+
+    fn set(&mut self, cell: &na::Vector2<i32>, block: Option<Block>, revision: u64) -> Option<Block> {
+        let index = index_in_chunk(cell);
+        let old = ::std::mem::replace(&mut self.cells[index], block);
+
+        if old.is_some() {
+            self.block_count -= 1;
+        }
+        if self.cells[index].is_some() {
+            self.block_count += 1;
+        }
+        self.revision = revision;
+        old
+    }
+}
+
+
+/**
+  The blocks of a level, stored in chunks of cells so that blocks near each
+  other are stored together and large empty areas take no space. The grid
+  only knows which blocks are where, see `GridRenderer` for drawing it.
+*/
 pub struct Grid {
-    pub blocks: HashMap<na::Vector2<i32>, Block>
+    chunks: HashMap<na::Vector2<i32>, Chunk>,
+    block_count: usize,
+    revision: u64
 }
 
 impl Grid {
     pub fn new() -> Self {
         Self {
-            blocks: HashMap::new()
+            chunks: HashMap::new(),
+            block_count: 0,
+            revision: 0
+        }
+    }
+
+    pub fn get(&self, cell: &na::Vector2<i32>) -> Option<&Block> {
+        self.chunks.get(&chunk_of(cell)).and_then(|chunk| chunk.get(cell))
+    }
+
+    pub fn contains(&self, cell: &na::Vector2<i32>) -> bool {
+        self.get(cell).is_some()
+    }
+
+    /**
+      Places a block in a cell, returning the block that was there before
+    */
+    pub fn insert(&mut self, cell: na::Vector2<i32>, block: Block) -> Option<Block> {
+        let position = chunk_of(&cell);
+        self.revision += 1;
+        let revision = self.revision;
+        let old = self.chunks.entry(position)
+            .or_insert_with(|| Chunk::new(position))
+            .set(&cell, Some(block), revision);
+
+        if old.is_none() {
+            self.block_count += 1;
         }
+        old
     }
-    pub fn add_prefab(
-            mut self,
+
+    /**
+      Removes the block in a cell. Chunks without blocks are removed as well
+    */
+    pub fn remove(&mut self, cell: &na::Vector2<i32>) -> Option<Block> {
+        let position = chunk_of(cell);
+        let revision = self.revision + 1;
+        let (old, now_empty) = match self.chunks.get_mut(&position) {
+            Some(chunk) => {
+                if chunk.get(cell).is_none() {
+                    return None;
+                }
+                let old = chunk.set(cell, None, revision);
+                (old, chunk.is_empty())
+            }
+            None => return None
+        };
+
+        if now_empty {
+            self.chunks.remove(&position);
+        }
+        self.revision = revision;
+        self.block_count -= 1;
+        old
+    }
+
+    pub fn len(&self) -> usize {
+        self.block_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.block_count == 0
+    }
+
+    pub fn chunk(&self, position: &na::Vector2<i32>) -> Option<&Chunk> {
+        self.chunks.get(position)
+    }
+
+    /**
+      Returns all chunks which contain at least one block
+    */
+    pub fn chunks(&self) -> Vec<&Chunk> {
+        self.chunks.values().collect()
+    }
+
+    /**
+      Returns every block in the grid along with its cell, in no particular
+      order
+    */
+    pub fn blocks(&self) -> Vec<(na::Vector2<i32>, &Block)> {
+        self.chunks.values().flat_map(|chunk| chunk.blocks()).collect()
+    }
+
+    pub fn add_prefab(&mut self, blocks: Vec<(BlockType, na::Vector2<i32>)>, offset: na::Vector2<i32>) {
+        self.add_blocks_on_layer(blocks, offset, Layer::Tiles);
+    }
+
+    fn add_blocks_on_layer(
+            &mut self,
             blocks: Vec<(BlockType, na::Vector2<i32>)>,
             offset: na::Vector2<i32>,
-            sprite_factory: &SpriteFactory,
-            textures: &HashMap<BlockType, Vec<Arc<glium::texture::SrgbTexture2d>>>
+            layer: Layer
         )
     {
-        let actual_pos = blocks.iter()
-            .map(|&(ref t, ref pos)| (t, pos+offset))
+        for (block_type, pos) in blocks {
+            self.insert(pos + offset, Block{block_type, layer});
+        }
+    }
+
+    /**
+      Adds the blocks of `prefab` moved by `offset` to the grid. Blocks which
+      overlap existing blocks are handled according to `policy`
+    */
+    pub fn stamp_prefab(
+            &mut self,
+            prefab: &Prefab,
+            offset: na::Vector2<i32>,
+            policy: ConflictPolicy
+        ) -> Result<(), PrefabConflict>
+    {
+        let blocks = prefab::resolve_conflicts(
+                prefab.placed_blocks(offset),
+                policy,
+                |pos| self.contains(pos)
+            )?;
+
+        self.add_blocks_on_layer(blocks, na::zero(), prefab.layer());
+        Ok(())
+    }
+
+    /**
+      Adds all the blocks described by a level file to the grid
+    */
+    pub fn load_level(&mut self, source: &str) -> Result<(), LevelError> {
+        let blocks = level::parse_level(source)?;
+        self.add_prefab(blocks, na::zero());
+        Ok(())
+    }
+
+    /**
+      Returns the type and position of every block in the grid, sorted by
+      row and then by column
+    */
+    pub fn block_list(&self) -> Vec<(BlockType, na::Vector2<i32>)> {
+        let mut result = self.blocks()
+            .into_iter()
+            .map(|(pos, block)| (block.block_type, pos))
+            .collect::<Vec<_>>();
+
+        result.sort_by_key(|&(_, pos)| (pos.y, pos.x));
+        result
+    }
+
+    /**
+      Returns the first and last cell containing a block, both inclusive
+    */
+    pub fn bounding_box(&self) -> Option<(na::Vector2<i32>, na::Vector2<i32>)> {
+        let cells = self.blocks().into_iter().map(|(pos, _)| pos).collect::<Vec<_>>();
+        cell_bounds(&cells)
+    }
+
+    /**
+      Returns the blocks between two cells, both inclusive. Only the chunks
+      overlapping the region are looked at
+    */
+    pub fn blocks_in_region(&self, min: na::Vector2<i32>, max: na::Vector2<i32>)
+        -> Vec<(na::Vector2<i32>, &Block)>
+    {
+        let mut result = vec!();
+        if min.x > max.x || min.y > max.y {
+            return result;
+        }
+
+        let (min_chunk, max_chunk) = (chunk_of(&min), chunk_of(&max));
+        for chunk_y in min_chunk.y..max_chunk.y + 1 {
+            for chunk_x in min_chunk.x..max_chunk.x + 1 {
+                let chunk = match self.chunks.get(&na::Vector2::new(chunk_x, chunk_y)) {
+                    Some(chunk) => chunk,
+                    None => continue
+                };
+
+                let (chunk_min, chunk_max) = chunk.cell_bounds();
+                for y in min.y.max(chunk_min.y)..max.y.min(chunk_max.y) + 1 {
+                    for x in min.x.max(chunk_min.x)..max.x.min(chunk_max.x) + 1 {
+                        let cell = na::Vector2::new(x, y);
+                        if let Some(block) = chunk.get(&cell) {
+                            result.push((cell, block));
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /**
+      Returns the blocks in the cells overlapping `view`. Blocks are assumed
+      to be drawn within their cell
+    */
+    pub fn blocks_in_view(&self, view: &Aabb) -> Vec<(na::Vector2<i32>, &Block)> {
+        let (min, max) = view.cell_bounds();
+        self.blocks_in_region(min, max)
+    }
+
+    pub fn to_level_string(&self) -> String {
+        level::write_level(&self.block_list())
+    }
+}
+
+impl BlockLookup for Grid {
+    fn block_type(&self, position: &na::Vector2<i32>) -> Option<BlockType> {
+        self.get(position).map(|block| block.block_type)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(block_type: BlockType) -> Block {
+        Block{block_type, layer: Layer::Tiles}
+    }
+
+    #[test]
+    fn cells_are_split_into_chunks() {
+        assert_eq!(chunk_of(&na::Vector2::new(0, 31)), na::Vector2::new(0, 0));
+        assert_eq!(chunk_of(&na::Vector2::new(32, -1)), na::Vector2::new(1, -1));
+        assert_eq!(chunk_of(&na::Vector2::new(-32, -33)), na::Vector2::new(-1, -2));
+        assert_eq!(index_in_chunk(&na::Vector2::new(-1, -1)), (CHUNK_SIZE * CHUNK_SIZE - 1) as usize);
+    }
+
+    #[test]
+    fn blocks_can_be_added_and_removed_across_chunks() {
+        let mut grid = Grid::new();
+        let cells = [
+            na::Vector2::new(0, 0), na::Vector2::new(-1, 0),
+            na::Vector2::new(31, 32), na::Vector2::new(-100, 70)
+        ];
+        for cell in &cells {
+            assert_eq!(grid.insert(*cell, block(BlockType::Stone)), None);
+        }
+        assert_eq!(grid.insert(cells[0], block(BlockType::StoneLadder)), Some(block(BlockType::Stone)));
+
+        assert_eq!(grid.len(), 4);
+        assert_eq!(grid.chunks().len(), 4);
+        assert_eq!(grid.block_type(&cells[0]), Some(BlockType::StoneLadder));
+        assert_eq!(grid.block_type(&cells[3]), Some(BlockType::Stone));
+        assert_eq!(grid.bounding_box(), Some((na::Vector2::new(-100, 0), na::Vector2::new(31, 70))));
+
+        assert_eq!(grid.remove(&cells[3]), Some(block(BlockType::Stone)));
+        assert_eq!(grid.remove(&cells[3]), None);
+        assert_eq!(grid.len(), 3);
+        assert!(grid.chunk(&chunk_of(&cells[3])).is_none());
+    }
+
+    #[test]
+    fn region_queries_only_return_blocks_in_the_region() {
+        let mut grid = Grid::new();
+        for x in -40..40 {
+            for y in -3..3 {
+                grid.insert(na::Vector2::new(x, y * 20), block(BlockType::Stone));
+            }
+        }
+
+        let mut found = grid.blocks_in_region(na::Vector2::new(-35, -20), na::Vector2::new(33, 0))
+            .into_iter()
+            .map(|(cell, _)| cell)
             .collect::<Vec<_>>();
+        found.sort_by_key(|cell| (cell.y, cell.x));
 
-        for (block_type, pos) in actual_pos {
-            let sprite = sprite_for_block(&block_type, pos, sprite_factory, textures);
-            self.blocks.insert(pos, Block{block_type: block_type.clone(), sprite});
+        let mut expected = vec!();
+        for &y in &[-20, 0] {
+            for x in -35..34 {
+                expected.push(na::Vector2::new(x, y));
+            }
         }
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn chunk_revisions_change_with_their_blocks() {
+        let mut grid = Grid::new();
+        grid.insert(na::Vector2::new(1, 1), block(BlockType::Stone));
+        grid.insert(na::Vector2::new(40, 1), block(BlockType::Stone));
+        let revision = grid.chunk(&na::zero()).unwrap().revision();
+        let other_revision = grid.chunk(&na::Vector2::new(1, 0)).unwrap().revision();
+
+        grid.insert(na::Vector2::new(2, 1), block(BlockType::StoneLadder));
+
+        assert!(grid.chunk(&na::zero()).unwrap().revision() > revision);
+        assert_eq!(grid.chunk(&na::Vector2::new(1, 0)).unwrap().revision(), other_revision);
     }
 }
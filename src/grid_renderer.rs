@@ -0,0 +1,330 @@
+/*!
+  Drawing the blocks of a `Grid`.
+
+  Every chunk of the grid gets vertex buffers with a quad for each of its
+  blocks, one buffer per layer and texture. The buffers are only rebuilt when
+  the revision of their chunk changes, and only chunks inside the view of the
+  camera are drawn.
+*/
+extern crate nalgebra as na;
+
+use glium;
+use glium::Surface;
+use glium::backend::{Context, Facade};
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::SrgbTexture2d;
+use glium::draw_parameters::DrawParameters;
+
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use camera_state::CameraState;
+use collision::Aabb;
+use constants::{DEFAULT_FRAGMENT_SHADER, DEFAULT_VERTEX_SHADER};
+use coordinates;
+use culling::CullingStats;
+use drawable::Drawable;
+use glium_types::Vertex;
+use grid::{self, BlockType, Chunk, Grid};
+use layer::Layer;
+use render_steps::RenderSteps;
+use sprite::SpriteTexture;
+
+
+/**
+  Picks one of `count` texture variants for a cell. The same cell always
+  gets the same variant so rebuilding a chunk does not change how it looks
+*/
+pub fn variant_index(cell: &na::Vector2<i32>, count: usize) -> usize
+{
+    let mut hash = (cell.x as u32).wrapping_mul(73856093) ^ (cell.y as u32).wrapping_mul(19349663);
+    hash = (hash ^ (hash >> 15)).wrapping_mul(2246822519);
+    hash = hash ^ (hash >> 13);
+    hash as usize % count
+}
+
+/**
+  The two triangles of a block in `cell` drawn with a texture of `size`
+  pixels, in world pixels. The quad starts at the top left corner of the
+  cell like block sprites do
+*/
+pub fn block_quad(cell: &na::Vector2<i32>, size: (u32, u32)) -> [Vertex; 6]
+{
+    let corner = grid::cell_to_world(*cell);
+    let (width, height) = (size.0 as f32, size.1 as f32);
+    let vertex = |u: f32, v: f32| Vertex {
+        position: (corner.x + u * width, corner.y + v * height),
+        tex_coords: (u, v)
+    };
+
+    [
+        vertex(0., 0.), vertex(0., 1.), vertex(1., 0.),
+        vertex(0., 1.), vertex(1., 1.), vertex(1., 0.),
+    ]
+}
+
+
+struct MeshBatch
+{
+    layer: Layer,
+    texture: Arc<SrgbTexture2d>,
+    vertices: glium::VertexBuffer<Vertex>,
+}
+
+struct ChunkMesh
+{
+    revision: u64,
+    /// Box around all quads of the chunk, which can be larger than the
+    /// chunk if textures are larger than blocks
+    bounds: Option<Aabb>,
+    batches: Vec<MeshBatch>,
+}
+
+
+pub struct GridRenderer
+{
+    context: Rc<Context>,
+    shader: glium::Program,
+    textures: HashMap<BlockType, Vec<Arc<SrgbTexture2d>>>,
+
+    chunks: HashMap<na::Vector2<i32>, ChunkMesh>,
+    /// Chunks drawn and culled by the last call to `draw`
+    stats: Cell<CullingStats>,
+}
+
+impl GridRenderer
+{
+    pub fn new(facade: &Facade, textures: HashMap<BlockType, Vec<Arc<SrgbTexture2d>>>) -> GridRenderer
+    {
+        let shader = glium::Program::from_source(
+                    facade,
+                    DEFAULT_VERTEX_SHADER,
+                    DEFAULT_FRAGMENT_SHADER,
+                    None
+                ).unwrap();
+
+        GridRenderer {
+            context: facade.get_context().clone(),
+            shader: shader,
+            textures: textures,
+
+            chunks: HashMap::new(),
+            stats: Cell::new(CullingStats::new()),
+        }
+    }
+
+    /**
+      Rebuilds the vertex buffers of chunks which changed since the last
+      update and forgets chunks which are no longer in the grid. Returns the
+      number of rebuilt chunks
+    */
+    pub fn update(&mut self, grid: &Grid) -> usize
+    {
+        let chunks = grid.chunks();
+
+        let positions = chunks.iter().map(|chunk| chunk.position()).collect::<HashSet<_>>();
+        self.chunks.retain(|position, _| positions.contains(position));
+
+        let mut rebuilt = 0;
+        for chunk in chunks
+        {
+            let up_to_date = self.chunks.get(&chunk.position())
+                .map(|mesh| mesh.revision == chunk.revision())
+                .unwrap_or(false);
+
+            if !up_to_date
+            {
+                let mesh = self.build_mesh(chunk);
+                self.chunks.insert(chunk.position(), mesh);
+                rebuilt += 1;
+            }
+        }
+        rebuilt
+    }
+
+    /**
+      The number of chunks drawn and skipped by the last draw
+    */
+    pub fn stats(&self) -> CullingStats
+    {
+        self.stats.get()
+    }
+
+    fn build_mesh(&self, chunk: &Chunk) -> ChunkMesh
+    {
+        // Vertices grouped by layer and texture, in the order the groups are
+        // first seen so that building the same chunk twice gives the same
+        // buffers
+        let mut groups: Vec<(Layer, Arc<SrgbTexture2d>, Vec<Vertex>)> = vec!();
+        let mut corners = vec!();
+
+        for (cell, block) in chunk.blocks()
+        {
+            let variants = match self.textures.get(&block.block_type)
+            {
+                Some(variants) if !variants.is_empty() => variants,
+                _ => continue
+            };
+            let texture = &variants[variant_index(&cell, variants.len())];
+            let quad = block_quad(&cell, SpriteTexture::whole(texture.clone()).size);
+
+            corners.push(na::Vector2::new(quad[0].position.0, quad[0].position.1));
+            corners.push(na::Vector2::new(quad[4].position.0, quad[4].position.1));
+
+            let group = groups.iter()
+                .position(|&(layer, ref group_texture, _)| {
+                    layer == block.layer && Arc::ptr_eq(group_texture, texture)
+                });
+            match group
+            {
+                Some(index) => groups[index].2.extend_from_slice(&quad),
+                None => groups.push((block.layer, texture.clone(), quad.to_vec()))
+            }
+        }
+
+        let batches = groups.into_iter()
+            .map(|(layer, texture, vertices)| MeshBatch {
+                layer: layer,
+                texture: texture,
+                vertices: glium::VertexBuffer::new(&self.context, &vertices).unwrap()
+            })
+            .collect();
+
+        ChunkMesh {
+            revision: chunk.revision(),
+            bounds: if corners.is_empty() { None } else { Some(Aabb::around_points(&corners)) },
+            batches: batches,
+        }
+    }
+}
+
+impl Drawable for GridRenderer
+{
+    fn draw(&self, target: &mut SimpleFrameBuffer, step: &RenderSteps, camera_state: &CameraState)
+    {
+        if *step != RenderSteps::Diffuse
+        {
+            return;
+        }
+
+        let target_size = target.get_dimensions();
+        let view = coordinates::visible_world_rect(camera_state, target_size);
+
+        let mut stats = CullingStats::new();
+        let mut batches = vec!();
+        for mesh in self.chunks.values()
+        {
+            match mesh.bounds
+            {
+                Some(bounds) if bounds.intersects(&view) => {
+                    stats.drawn += 1;
+                    batches.extend(mesh.batches.iter());
+                }
+                _ => stats.culled += 1
+            }
+        }
+        self.stats.set(stats);
+
+        batches.sort_by_key(|batch| batch.layer);
+
+        let matrix_data: [[f32; 4]; 4] = *coordinates::world_to_clip_matrix(camera_state, target_size).as_ref();
+        let draw_parameters = DrawParameters {
+            blend: glium::draw_parameters::Blend::alpha_blending(),
+            .. Default::default()
+        };
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+
+        for batch in batches
+        {
+            let uniforms = uniform! {
+                matrix: matrix_data,
+                uv_rect: (0., 0., 1., 1.),
+                tint: (1., 1., 1., 1.),
+                tex: batch.texture.sampled()
+                    .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            };
+
+            target.draw(&batch.vertices, &indices, &self.shader, &uniforms, &draw_parameters).unwrap();
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use glium::texture::RawImage2d;
+    use grid::Block;
+    use headless;
+    use sprite::SpriteFactory;
+
+    #[test]
+    fn variants_are_stable_and_in_range()
+    {
+        for x in -40..40
+        {
+            for y in -40..40
+            {
+                let cell = na::Vector2::new(x, y);
+                let index = variant_index(&cell, 3);
+                assert!(index < 3);
+                assert_eq!(index, variant_index(&cell, 3));
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_draw_the_same_as_block_sprites()
+    {
+        let renderer = headless::test_renderer(64, 64);
+
+        let pixels = (0..8 * 8)
+            .flat_map(|i| vec!(255 - (i * 4) as u8, (i * 4) as u8, 64, 255))
+            .collect::<Vec<_>>();
+        let texture = Arc::new(
+            SrgbTexture2d::new(&renderer, RawImage2d::from_raw_rgba(pixels, (8, 8))).unwrap()
+        );
+        let mut textures = HashMap::new();
+        textures.insert(BlockType::Stone, vec!(texture.clone()));
+
+        // Blocks on both sides of a chunk border
+        let cells = vec!(na::Vector2::new(-1, 0), na::Vector2::new(0, 0), na::Vector2::new(0, 1));
+        let mut grid = Grid::new();
+        for cell in &cells
+        {
+            grid.insert(*cell, Block{block_type: BlockType::Stone, layer: Layer::Tiles});
+        }
+
+        let mut camera_state = CameraState::new();
+        camera_state.set_position(na::Vector2::new(-1., 0.5));
+        camera_state.set_zoom(0.5);
+
+        let factory = SpriteFactory::new(&renderer);
+        let individual = headless::render_to_image(&renderer, (64, 64), |target| {
+            for cell in &cells
+            {
+                let mut sprite = factory.create_sprite(texture.clone());
+                sprite.set_position(grid::cell_to_world(*cell));
+                sprite.draw(target, &RenderSteps::Diffuse, &camera_state);
+            }
+        });
+
+        let mut grid_renderer = GridRenderer::new(&renderer, textures);
+        assert_eq!(grid_renderer.update(&grid), 2);
+        assert_eq!(grid_renderer.update(&grid), 0);
+
+        let chunked = headless::render_to_image(&renderer, (64, 64), |target| {
+            grid_renderer.draw(target, &RenderSteps::Diffuse, &camera_state);
+        });
+
+        assert_eq!(headless::compare_images(&individual, &chunked, 1), Ok(()));
+
+        grid.remove(&na::Vector2::new(-1, 0));
+        assert_eq!(grid_renderer.update(&grid), 0);
+        assert_eq!(grid_renderer.chunks.len(), 1);
+    }
+}
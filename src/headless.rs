@@ -0,0 +1,417 @@
+/*!
+  Rendering without a window, for testing the drawing code.
+
+  On machines without a GPU the headless context is created by the software
+  rasteriser of Mesa (OSMesa). Rendered images are compared against reference
+  images which are committed in `tests/reference`. A missing reference is an
+  error, unless the environment variable `UPDATE_REFERENCES` is set to `1` in
+  which case the rendered image is saved as the reference so that it can be
+  checked by eye and committed.
+*/
+use glium;
+use glium::glutin;
+use glium::texture::texture2d::Texture2d;
+use glium::backend::Facade;
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::Surface;
+
+use image::{self, RgbaImage};
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use drawing_util;
+
+
+#[derive(Debug)]
+pub enum HeadlessError
+{
+    Context(glutin::CreationError),
+    IncompatibleOpenGl(glium::IncompatibleOpenGl),
+}
+
+impl fmt::Display for HeadlessError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            HeadlessError::Context(ref e) => write!(f, "Failed to create headless context: {}", e),
+            HeadlessError::IncompatibleOpenGl(ref e) => write!(f, "Incompatible OpenGL: {:?}", e),
+        }
+    }
+}
+
+impl Error for HeadlessError
+{
+    fn description(&self) -> &str
+    {
+        match *self
+        {
+            HeadlessError::Context(_) => "failed to create headless context",
+            HeadlessError::IncompatibleOpenGl(_) => "incompatible OpenGL",
+        }
+    }
+}
+
+
+/**
+  Creates an OpenGL context which is not attached to a window
+*/
+pub fn create_renderer(width: u32, height: u32) -> Result<glium::HeadlessRenderer, HeadlessError>
+{
+    let context = glutin::HeadlessRendererBuilder::new(width, height)
+        .build()
+        .map_err(HeadlessError::Context)?;
+
+    glium::HeadlessRenderer::new(context).map_err(HeadlessError::IncompatibleOpenGl)
+}
+
+/**
+  Creates a renderer for rendering tests. Machines that run the tests must be
+  able to create a context, if necessary with OSMesa, so failing to create
+  one fails the test instead of skipping it
+*/
+#[cfg(test)]
+pub fn test_renderer(width: u32, height: u32) -> glium::HeadlessRenderer
+{
+    create_renderer(width, height)
+        .unwrap_or_else(|e| panic!("Rendering tests need an OpenGL context: {}", e))
+}
+
+/**
+  Calls `draw` with a cleared texture of the specified size as the target and
+  returns what was drawn
+*/
+pub fn render_to_image<F>(facade: &Facade, size: (u32, u32), draw: F) -> RgbaImage
+    where F: FnOnce(&mut SimpleFrameBuffer)
+{
+    let texture = Texture2d::empty(facade, size.0, size.1).unwrap();
+    {
+        let mut target = texture.as_surface();
+        target.clear_color(0., 0., 0., 0.);
+        draw(&mut target);
+    }
+
+    drawing_util::texture_to_image(&texture)
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageMismatch
+{
+    Size{expected: (u32, u32), actual: (u32, u32)},
+    /// `count` pixels had a channel which differed by more than the
+    /// tolerance, by at most `max_difference`
+    Pixels{count: usize, max_difference: u8},
+}
+
+impl fmt::Display for ImageMismatch
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            ImageMismatch::Size{expected, actual} =>
+                write!(f, "Expected a {}x{} image, got {}x{}", expected.0, expected.1, actual.0, actual.1),
+            ImageMismatch::Pixels{count, max_difference} =>
+                write!(f, "{} pixels differ, by up to {}", count, max_difference),
+        }
+    }
+}
+
+impl Error for ImageMismatch
+{
+    fn description(&self) -> &str
+    {
+        "images differ"
+    }
+}
+
+/**
+  Checks that every channel of every pixel of `actual` is within `tolerance`
+  of `expected`. Different GPUs and drivers rasterise slightly differently so
+  some tolerance is usually needed
+*/
+pub fn compare_images(expected: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> Result<(), ImageMismatch>
+{
+    if expected.dimensions() != actual.dimensions()
+    {
+        return Err(ImageMismatch::Size{expected: expected.dimensions(), actual: actual.dimensions()});
+    }
+
+    let mut count = 0;
+    let mut max_difference = 0;
+    for (expected, actual) in expected.pixels().zip(actual.pixels())
+    {
+        let difference = expected.data.iter()
+            .zip(actual.data.iter())
+            .map(|(a, b)| (*a as i32 - *b as i32).abs() as u8)
+            .max()
+            .unwrap_or(0);
+
+        if difference > tolerance
+        {
+            count += 1;
+            max_difference = max_difference.max(difference);
+        }
+    }
+
+    if count == 0
+    {
+        Ok(())
+    }
+    else
+    {
+        Err(ImageMismatch::Pixels{count, max_difference})
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ReferenceError
+{
+    Io(io::Error),
+    Image(image::ImageError),
+    /// There is no reference image at the path and `UPDATE_REFERENCES` is
+    /// not set
+    Missing(PathBuf),
+    /// The image did not match the reference. The rendered image was saved
+    /// next to it with the extension `.actual.png`
+    Mismatch(ImageMismatch),
+}
+
+impl fmt::Display for ReferenceError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            ReferenceError::Io(ref e) => write!(f, "{}", e),
+            ReferenceError::Image(ref e) => write!(f, "{}", e),
+            ReferenceError::Missing(ref path) => write!(
+                    f,
+                    "Reference image {} does not exist, run with {}=1 to create it",
+                    path.display(),
+                    UPDATE_REFERENCES_VARIABLE
+                ),
+            ReferenceError::Mismatch(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ReferenceError
+{
+    fn description(&self) -> &str
+    {
+        match *self
+        {
+            ReferenceError::Io(ref e) => e.description(),
+            ReferenceError::Image(ref e) => e.description(),
+            ReferenceError::Missing(_) => "missing reference image",
+            ReferenceError::Mismatch(ref e) => e.description(),
+        }
+    }
+}
+
+impl From<io::Error> for ReferenceError
+{
+    fn from(e: io::Error) -> Self
+    {
+        ReferenceError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ReferenceError
+{
+    fn from(e: image::ImageError) -> Self
+    {
+        ReferenceError::Image(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReferenceResult
+{
+    Matched,
+    /// There was no reference and `UPDATE_REFERENCES` was set so the image
+    /// was saved as the new reference
+    Created,
+}
+
+/// Setting this environment variable to `1` makes `check_reference` save
+/// missing reference images instead of failing
+pub const UPDATE_REFERENCES_VARIABLE: &'static str = "UPDATE_REFERENCES";
+
+/**
+  Compares `image` to the reference image at `path`. If there is none it is
+  either an error or, if `UPDATE_REFERENCES` is set, `image` becomes the
+  reference
+*/
+pub fn check_reference(image: &RgbaImage, path: &Path, tolerance: u8)
+    -> Result<ReferenceResult, ReferenceError>
+{
+    if !path.exists()
+    {
+        if env::var(UPDATE_REFERENCES_VARIABLE).ok().as_ref().map(|value| value.as_str()) != Some("1")
+        {
+            return Err(ReferenceError::Missing(path.to_path_buf()));
+        }
+
+        image.save(path)?;
+        return Ok(ReferenceResult::Created);
+    }
+
+    let reference = image::open(path)?.to_rgba();
+    match compare_images(&reference, image, tolerance)
+    {
+        Ok(()) => Ok(ReferenceResult::Matched),
+        Err(mismatch) => {
+            image.save(path.with_extension("actual.png"))?;
+            Err(ReferenceError::Mismatch(mismatch))
+        }
+    }
+}
+
+/**
+  The path of a reference image in the directory of reference images
+*/
+pub fn reference_path(name: &str) -> PathBuf
+{
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("reference")
+        .join(name)
+        .with_extension("png")
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+    use image::Rgba;
+
+    use camera_state::CameraState;
+    use drawable::Drawable;
+    use line::Line;
+    use rendering::{RenderProcess, RenderStep};
+    use render_steps::{self, RenderParameters, RenderSteps, PostProcessSettings};
+
+    use std::fs;
+
+    const TOLERANCE: u8 = 2;
+
+    fn assert_matches_reference(image: &RgbaImage, name: &str)
+    {
+        let path = reference_path(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        if let Err(e) = check_reference(image, &path, TOLERANCE)
+        {
+            panic!("{} does not match the reference: {}", name, e);
+        }
+    }
+
+    #[test]
+    fn images_within_tolerance_match()
+    {
+        let expected = RgbaImage::from_pixel(4, 4, Rgba{data: [100, 100, 100, 255]});
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 1, Rgba{data: [102, 99, 100, 255]});
+
+        assert_eq!(compare_images(&expected, &actual, 2), Ok(()));
+
+        actual.put_pixel(2, 2, Rgba{data: [100, 100, 110, 255]});
+        assert_eq!(
+            compare_images(&expected, &actual, 2),
+            Err(ImageMismatch::Pixels{count: 1, max_difference: 10})
+        );
+    }
+
+    #[test]
+    fn images_of_different_sizes_do_not_match()
+    {
+        let expected = RgbaImage::new(4, 4);
+        let actual = RgbaImage::new(4, 5);
+
+        assert_eq!(
+            compare_images(&expected, &actual, 255),
+            Err(ImageMismatch::Size{expected: (4, 4), actual: (4, 5)})
+        );
+    }
+
+    #[test]
+    fn missing_references_are_errors()
+    {
+        let path = reference_path("does_not_exist");
+        let image = RgbaImage::new(4, 4);
+
+        if env::var(UPDATE_REFERENCES_VARIABLE).is_err()
+        {
+            match check_reference(&image, &path, 0)
+            {
+                Err(ReferenceError::Missing(ref missing)) => assert_eq!(missing, &path),
+                _ => panic!("Expected missing reference error")
+            }
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn lines_match_reference()
+    {
+        let renderer = test_renderer(64, 64);
+
+        let line = Line::new(&renderer, na::Vector2::new(8., 8.), na::Vector2::new(56., 40.))
+            .with_color((1., 0.5, 0., 1.));
+        let image = render_to_image(&renderer, (64, 64), |target| {
+            line.draw(target, &RenderSteps::Diffuse, &CameraState::new());
+        });
+
+        assert_matches_reference(&image, "line");
+    }
+
+    #[test]
+    fn post_processing_matches_reference()
+    {
+        let renderer = test_renderer(64, 64);
+
+        let output = Texture2d::empty(&renderer, 64, 64).unwrap();
+        output.as_surface().clear_color(0., 0., 0., 0.);
+        let process = RenderProcess::new(
+                &renderer,
+                RenderSteps::get_hash_set(),
+                RenderParameters::new(&renderer, (64, 64)),
+                render_steps::DEFAULT_FRAGMENT_SHADER,
+                render_steps::default_render_function
+            );
+        process.uniforms().set_settings(PostProcessSettings {
+            ambient: (0.25, 0.25, 0.25),
+            .. Default::default()
+        });
+
+        let image = {
+            let mut targets = process.get_targets();
+            targets.get_mut(&RenderSteps::Diffuse).unwrap().clear_color(1., 0.5, 0., 1.);
+            targets.get_mut(&RenderSteps::Light).unwrap().clear_color(0.5, 0.5, 0.5, 1.);
+            targets.get_mut(&RenderSteps::Emissive).unwrap().clear_color(0., 0., 0., 0.);
+
+            // Lines are only drawn in the diffuse step so the line is drawn
+            // straight into the emissive target to give the bloom something
+            let line = Line::new(&renderer, na::Vector2::new(0., 32.), na::Vector2::new(64., 32.));
+            let emissive = targets.get_mut(&RenderSteps::Emissive).unwrap();
+            line.draw(emissive, &RenderSteps::Diffuse, &CameraState::new());
+
+            process.draw_to_display(&mut output.as_surface());
+            drawing_util::texture_to_image(&output)
+        };
+
+        assert_matches_reference(&image, "post_processing");
+    }
+}
@@ -0,0 +1,49 @@
+/*!
+  Draw layers of sprites. Sprites on a later layer are drawn on top of the
+  sprites on earlier layers, regardless of their depth.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Layer
+{
+    Background,
+    Tiles,
+    Props,
+    Characters,
+    Foreground,
+    Ui,
+}
+
+impl Layer
+{
+    /// All layers from the back to the front
+    pub fn all() -> Vec<Layer>
+    {
+        vec!(
+            Layer::Background,
+            Layer::Tiles,
+            Layer::Props,
+            Layer::Characters,
+            Layer::Foreground,
+            Layer::Ui
+        )
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match *self
+        {
+            Layer::Background => "background",
+            Layer::Tiles => "tiles",
+            Layer::Props => "props",
+            Layer::Characters => "characters",
+            Layer::Foreground => "foreground",
+            Layer::Ui => "ui",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layer>
+    {
+        Layer::all().into_iter().find(|layer| layer.name() == name)
+    }
+}
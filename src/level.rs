@@ -0,0 +1,492 @@
+/*!
+  Reading and writing of the text based level format.
+
+  A level file consists of an origin, a legend which maps single characters
+  to block types and an ascii map of the level. Lines starting with `//`
+  before the map are comments, inside the map they are rows of blocks. Empty
+  cells are written as `.`
+
+  ```text
+  // The grid position of the top left character in the map
+  origin -2 0
+  legend
+  # = Stone
+  H = StoneLadder
+  map
+  #####
+  #H..#
+  ```
+
+  Writing a list of blocks and reading it back gives the same list, and
+  levels written by `write_level` are stable so they can be diffed.
+*/
+extern crate nalgebra as na;
+
+use grid::BlockType;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+
+pub const EMPTY_SYMBOL: char = '.';
+pub const FILE_EXTENSION: &'static str = "level";
+const COMMENT_PREFIX: &'static str = "//";
+
+
+#[derive(Debug, PartialEq)]
+pub enum LevelErrorKind {
+    UnknownBlockType(String),
+    UnknownSymbol(char),
+    DuplicateSymbol(char),
+    ReservedSymbol(char),
+    MalformedLegendEntry,
+    MalformedOrigin,
+    MissingSection(&'static str),
+    UnexpectedContent,
+}
+
+/**
+  An error encountered while parsing a level. `line` and `column` are 1-based
+*/
+#[derive(Debug, PartialEq)]
+pub struct LevelError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: LevelErrorKind
+}
+
+impl LevelError {
+    fn new(line: usize, column: usize, kind: LevelErrorKind) -> Self {
+        Self { line, column, kind }
+    }
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match self.kind {
+            LevelErrorKind::UnknownBlockType(ref name) =>
+                write!(f, "unknown block type '{}'", name),
+            LevelErrorKind::UnknownSymbol(symbol) =>
+                write!(f, "symbol '{}' is not in the legend", symbol),
+            LevelErrorKind::DuplicateSymbol(symbol) =>
+                write!(f, "symbol '{}' is defined more than once", symbol),
+            LevelErrorKind::ReservedSymbol(symbol) =>
+                write!(f, "symbol '{}' can not be used in the legend", symbol),
+            LevelErrorKind::MalformedLegendEntry =>
+                write!(f, "expected a legend entry of the form '<symbol> = <block type>'"),
+            LevelErrorKind::MalformedOrigin =>
+                write!(f, "expected 'origin <x> <y>'"),
+            LevelErrorKind::MissingSection(section) =>
+                write!(f, "missing '{}' section", section),
+            LevelErrorKind::UnexpectedContent =>
+                write!(f, "unexpected content"),
+        }
+    }
+}
+
+impl Error for LevelError {
+    fn description(&self) -> &str {
+        "failed to parse level"
+    }
+}
+
+
+#[derive(Debug)]
+pub enum LevelLoadError {
+    Io(io::Error),
+    Parse(LevelError)
+}
+
+impl fmt::Display for LevelLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LevelLoadError::Io(ref e) => write!(f, "failed to read level: {}", e),
+            LevelLoadError::Parse(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for LevelLoadError {
+    fn description(&self) -> &str {
+        match *self {
+            LevelLoadError::Io(ref e) => e.description(),
+            LevelLoadError::Parse(ref e) => e.description(),
+        }
+    }
+}
+
+impl From<io::Error> for LevelLoadError {
+    fn from(e: io::Error) -> Self {
+        LevelLoadError::Io(e)
+    }
+}
+
+impl From<LevelError> for LevelLoadError {
+    fn from(e: LevelError) -> Self {
+        LevelLoadError::Parse(e)
+    }
+}
+
+
+/**
+  The symbol used for a block type when writing levels
+*/
+pub fn default_symbol(block_type: &BlockType) -> char {
+    match *block_type {
+        BlockType::Stone => '#',
+        BlockType::StoneLadder => 'H'
+    }
+}
+
+
+enum Section {
+    Header,
+    Legend,
+    Map
+}
+
+/**
+  Parses a level file into a list of blocks
+*/
+pub fn parse_level(source: &str) -> Result<Vec<(BlockType, na::Vector2<i32>)>, LevelError> {
+    let mut section = Section::Header;
+    let mut origin = None;
+    let mut legend = HashMap::new();
+    let mut map_row = 0;
+    let mut result = vec!();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+
+        // Comments and empty lines are only allowed outside the map since
+        // they are meaningful rows inside it
+        let trimmed = line.trim();
+        match section {
+            Section::Map => {},
+            _ if trimmed.is_empty() || trimmed.starts_with(COMMENT_PREFIX) => continue,
+            _ => {}
+        }
+
+        match section {
+            Section::Header if trimmed == "legend" => {
+                if origin.is_none() {
+                    return Err(LevelError::new(line_number, 1, LevelErrorKind::MissingSection("origin")));
+                }
+                section = Section::Legend;
+            }
+            Section::Legend if trimmed == "legend" => {
+                return Err(LevelError::new(line_number, 1, LevelErrorKind::UnexpectedContent));
+            }
+            Section::Header
+                if trimmed.split_whitespace().next() == Some("origin") && origin.is_none() => {
+                origin = Some(parse_origin(trimmed)
+                    .ok_or(LevelError::new(line_number, 1, LevelErrorKind::MalformedOrigin))?);
+            }
+            Section::Header => {
+                return Err(LevelError::new(line_number, 1, LevelErrorKind::UnexpectedContent));
+            }
+            Section::Legend if trimmed == "map" => {
+                section = Section::Map;
+            }
+            Section::Legend => {
+                let (symbol, block_type) = parse_legend_entry(line, line_number)?;
+                if legend.insert(symbol, block_type).is_some() {
+                    return Err(LevelError::new(
+                            line_number,
+                            1,
+                            LevelErrorKind::DuplicateSymbol(symbol)
+                        ));
+                }
+            }
+            Section::Map => {
+                let origin: na::Vector2<i32> = origin.unwrap();
+                for (column, symbol) in line.trim_right().chars().enumerate() {
+                    if symbol == EMPTY_SYMBOL {
+                        continue;
+                    }
+
+                    let block_type = legend.get(&symbol)
+                        .ok_or(LevelError::new(
+                                line_number,
+                                column + 1,
+                                LevelErrorKind::UnknownSymbol(symbol)
+                            ))?;
+
+                    let position = origin + na::Vector2::new(column as i32, map_row);
+                    result.push((*block_type, position));
+                }
+                map_row += 1;
+            }
+        }
+    }
+
+    let last_line = source.lines().count();
+    match section {
+        Section::Header if origin.is_none() =>
+            Err(LevelError::new(last_line, 1, LevelErrorKind::MissingSection("origin"))),
+        Section::Header =>
+            Err(LevelError::new(last_line, 1, LevelErrorKind::MissingSection("legend"))),
+        Section::Legend =>
+            Err(LevelError::new(last_line, 1, LevelErrorKind::MissingSection("map"))),
+        Section::Map => Ok(result)
+    }
+}
+
+fn parse_origin(line: &str) -> Option<na::Vector2<i32>> {
+    let mut parts = line.split_whitespace().skip(1);
+
+    let x = parts.next().and_then(|x| x.parse().ok());
+    let y = parts.next().and_then(|y| y.parse().ok());
+
+    match (x, y, parts.next()) {
+        (Some(x), Some(y), None) => Some(na::Vector2::new(x, y)),
+        _ => None
+    }
+}
+
+fn parse_legend_entry(line: &str, line_number: usize)
+    -> Result<(char, BlockType), LevelError>
+{
+    let malformed = |column| LevelError::new(line_number, column, LevelErrorKind::MalformedLegendEntry);
+
+    let mut chars = line.trim_left().chars();
+
+    let symbol = chars.next().ok_or(malformed(1))?;
+    if symbol == EMPTY_SYMBOL || symbol.is_whitespace() {
+        let symbol_column = column_of(line, line.trim_left());
+        return Err(LevelError::new(line_number, symbol_column, LevelErrorKind::ReservedSymbol(symbol)));
+    }
+
+    let rest = chars.as_str();
+    let rest_column = column_of(line, rest);
+    let rest = rest.trim_left();
+    if !rest.starts_with('=') {
+        return Err(malformed(rest_column));
+    }
+
+    let name = rest[1..].trim();
+    let name_column = column_of(line, rest[1..].trim_left());
+    if name.is_empty() {
+        return Err(malformed(name_column));
+    }
+
+    BlockType::from_name(name)
+        .map(|block_type| (symbol, block_type))
+        .ok_or(LevelError::new(
+                line_number,
+                name_column,
+                LevelErrorKind::UnknownBlockType(name.to_string())
+            ))
+}
+
+/**
+  The 1-based column in `line` where `rest`, a suffix of it, starts. Columns
+  count characters rather than bytes
+*/
+fn column_of(line: &str, rest: &str) -> usize {
+    line[..line.len() - rest.len()].chars().count() + 1
+}
+
+
+/**
+  Writes a list of blocks in the level format. Only block types which are
+  present are added to the legend
+*/
+pub fn write_level(blocks: &[(BlockType, na::Vector2<i32>)]) -> String {
+    let cells = blocks.iter()
+        .map(|&(block_type, position)| (position, block_type))
+        .collect::<HashMap<_, _>>();
+
+    let mut result = String::new();
+
+    if cells.is_empty() {
+        result.push_str("origin 0 0\nlegend\nmap\n");
+        return result;
+    }
+
+    let min_x = cells.keys().map(|pos| pos.x).min().unwrap();
+    let min_y = cells.keys().map(|pos| pos.y).min().unwrap();
+    let max_x = cells.keys().map(|pos| pos.x).max().unwrap();
+    let max_y = cells.keys().map(|pos| pos.y).max().unwrap();
+
+    result.push_str(&format!("origin {} {}\n", min_x, min_y));
+
+    result.push_str("legend\n");
+    for block_type in BlockType::all() {
+        if cells.values().any(|t| *t == block_type) {
+            result.push_str(&format!("{} = {}\n", default_symbol(&block_type), block_type.name()));
+        }
+    }
+
+    result.push_str("map\n");
+    for y in min_y..max_y + 1 {
+        let mut row = (min_x..max_x + 1)
+            .map(|x| {
+                cells.get(&na::Vector2::new(x, y))
+                    .map(default_symbol)
+                    .unwrap_or(EMPTY_SYMBOL)
+            })
+            .collect::<String>();
+
+        // Trailing empty cells are implied
+        while row.ends_with(EMPTY_SYMBOL) {
+            row.pop();
+        }
+
+        result.push_str(&row);
+        result.push('\n');
+    }
+
+    result
+}
+
+
+pub fn load_level_file(path: &Path)
+    -> Result<Vec<(BlockType, na::Vector2<i32>)>, LevelLoadError>
+{
+    let mut source = String::new();
+    File::open(path)?.read_to_string(&mut source)?;
+
+    Ok(parse_level(&source)?)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+
+    const EXAMPLE: &'static str = "\
+// A small room
+origin -1 2
+legend
+# = Stone
+H = StoneLadder
+map
+###
+#H
+
+..#
+";
+
+    #[test]
+    fn parsing_reads_blocks_relative_to_origin()
+    {
+        let blocks = parse_level(EXAMPLE).unwrap();
+
+        assert_eq!(blocks, vec!(
+                (BlockType::Stone, na::Vector2::new(-1, 2)),
+                (BlockType::Stone, na::Vector2::new(0, 2)),
+                (BlockType::Stone, na::Vector2::new(1, 2)),
+                (BlockType::Stone, na::Vector2::new(-1, 3)),
+                (BlockType::StoneLadder, na::Vector2::new(0, 3)),
+                (BlockType::Stone, na::Vector2::new(1, 5)),
+            ));
+    }
+
+    #[test]
+    fn written_levels_round_trip()
+    {
+        let blocks = parse_level(EXAMPLE).unwrap();
+        let written = write_level(&blocks);
+
+        assert_eq!(parse_level(&written).unwrap(), blocks);
+        assert_eq!(write_level(&parse_level(&written).unwrap()), written);
+    }
+
+    #[test]
+    fn empty_levels_round_trip()
+    {
+        let written = write_level(&[]);
+        assert_eq!(parse_level(&written).unwrap(), vec!());
+    }
+
+    #[test]
+    fn unknown_block_types_report_their_position()
+    {
+        let source = "origin 0 0\nlegend\nx = Granite\nmap\n";
+
+        assert_eq!(parse_level(source), Err(LevelError {
+                line: 3,
+                column: 5,
+                kind: LevelErrorKind::UnknownBlockType("Granite".to_string())
+            }));
+    }
+
+    #[test]
+    fn unknown_symbols_report_their_position()
+    {
+        let source = "origin 0 0\nlegend\n# = Stone\nmap\n#.#\n##x\n";
+
+        assert_eq!(parse_level(source), Err(LevelError {
+                line: 6,
+                column: 3,
+                kind: LevelErrorKind::UnknownSymbol('x')
+            }));
+    }
+
+    #[test]
+    fn columns_count_characters()
+    {
+        let source = "origin 0 0\nlegend\nä = Granite\nmap\n";
+
+        assert_eq!(parse_level(source), Err(LevelError {
+                line: 3,
+                column: 5,
+                kind: LevelErrorKind::UnknownBlockType("Granite".to_string())
+            }));
+    }
+
+    #[test]
+    fn comment_prefix_is_a_row_inside_the_map()
+    {
+        let source = "origin 0 0\nlegend\n// Slashes are blocks too\n/ = Stone\nmap\n//\n";
+
+        assert_eq!(parse_level(source).unwrap(), vec!(
+                (BlockType::Stone, na::Vector2::new(0, 0)),
+                (BlockType::Stone, na::Vector2::new(1, 0)),
+            ));
+    }
+
+    #[test]
+    fn origin_must_be_a_whole_word()
+    {
+        let source = "originfoo 1 2\nlegend\nmap\n";
+
+        assert_eq!(parse_level(source), Err(LevelError {
+                line: 1,
+                column: 1,
+                kind: LevelErrorKind::UnexpectedContent
+            }));
+    }
+
+    #[test]
+    fn repeated_legend_headers_are_errors()
+    {
+        let source = "origin 0 0\nlegend\n# = Stone\nlegend\nmap\n";
+
+        assert_eq!(parse_level(source), Err(LevelError {
+                line: 4,
+                column: 1,
+                kind: LevelErrorKind::UnexpectedContent
+            }));
+    }
+
+    #[test]
+    fn missing_map_is_an_error()
+    {
+        let source = "origin 0 0\nlegend\n# = Stone\n";
+
+        assert_eq!(
+                parse_level(source).unwrap_err().kind,
+                LevelErrorKind::MissingSection("map")
+            );
+    }
+}
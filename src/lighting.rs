@@ -0,0 +1,568 @@
+/*!
+  Point lights which are blocked by the colliding blocks of the grid.
+
+  The amount of light at a point can also be computed without a GL context,
+  which lets gameplay code decide if something is hidden in the dark.
+
+  For every light the visible area around it is found by casting rays
+  towards the corners of the nearby blocks. The resulting polygon is drawn
+  into the `RenderSteps::Light` target with additive blending, and the post
+  processing shader multiplies the diffuse target with it.
+*/
+extern crate nalgebra as na;
+
+use glium;
+use glium::Surface;
+use glium::backend::{Context, Facade};
+use glium::framebuffer::SimpleFrameBuffer;
+
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use camera_state::CameraState;
+use drawing_util;
+use grid::{self, BlockLookup, BLOCK_SIZE};
+use raycast;
+
+
+/// Number of rays cast evenly around a light in addition to the ones
+/// towards block corners, so that the edge of the light is round
+const CIRCLE_RAYS: usize = 48;
+/// Angle offset of the extra rays cast on each side of a corner
+const CORNER_OFFSET: f32 = 0.0001;
+
+
+pub const LIGHT_VERTEX_SHADER: &'static str = r#"
+        #version 140
+        in vec2 position;
+        out vec2 world_position;
+        uniform mat4 matrix;
+        void main() {
+            world_position = position;
+            gl_Position = matrix * vec4(position, 0.0, 1.0);
+        }
+    "#;
+// The falloff must be kept in sync with `attenuation`
+pub const LIGHT_FRAGMENT_SHADER: &'static str = r#"
+        #version 140
+        in vec2 world_position;
+        out vec4 color;
+        uniform vec2 light_position;
+        uniform float radius;
+        uniform vec3 light_color;
+        uniform float intensity;
+        void main() {
+            float falloff = clamp(1. - distance(world_position, light_position) / radius, 0., 1.);
+            color = vec4(light_color * intensity * falloff * falloff, 1.);
+        }
+    "#;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShadowMode
+{
+    Hard,
+    /// Soft shadows are made by rendering the light from `samples` positions
+    /// spread over a disc with radius `source_radius`
+    Soft { source_radius: f32, samples: u32 }
+}
+
+/**
+  A light at a position in world pixels which lights everything within
+  `radius` pixels that it can see
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight
+{
+    pub position: na::Vector2<f32>,
+    pub radius: f32,
+    pub color: (f32, f32, f32),
+    pub intensity: f32,
+    pub shadows: ShadowMode
+}
+
+impl PointLight
+{
+    pub fn new(position: na::Vector2<f32>, radius: f32) -> PointLight
+    {
+        PointLight {
+            position: position,
+            radius: radius,
+            color: (1., 1., 1.),
+            intensity: 1.,
+            shadows: ShadowMode::Hard
+        }
+    }
+
+    pub fn with_color(mut self, color: (f32, f32, f32)) -> PointLight
+    {
+        self.color = color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> PointLight
+    {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_soft_shadows(mut self, source_radius: f32, samples: u32) -> PointLight
+    {
+        self.shadows = ShadowMode::Soft{source_radius, samples};
+        self
+    }
+
+    /**
+      Returns the positions that the light is rendered from. Hard shadows use
+      the position of the light while soft shadows use points spread evenly
+      over the light source
+    */
+    pub fn sample_positions(&self) -> Vec<na::Vector2<f32>>
+    {
+        match self.shadows
+        {
+            ShadowMode::Soft{source_radius, samples} if samples > 1 => {
+                // Points on a sunflower spiral are spread evenly over a disc
+                let golden_angle = PI * (3. - (5. as f32).sqrt());
+
+                (0..samples)
+                    .map(|i| {
+                        let distance = source_radius * ((i as f32 + 0.5) / samples as f32).sqrt();
+                        let angle = i as f32 * golden_angle;
+                        self.position + na::Vector2::new(angle.cos(), angle.sin()) * distance
+                    })
+                    .collect()
+            }
+            _ => vec!(self.position)
+        }
+    }
+}
+
+
+/**
+  The fraction of the light's intensity that reaches `distance` pixels away
+  from it if nothing is in the way
+*/
+pub fn attenuation(distance: f32, radius: f32) -> f32
+{
+    if radius <= 0. {
+        return 0.;
+    }
+
+    let falloff = (1. - distance / radius).max(0.).min(1.);
+    falloff * falloff
+}
+
+
+/**
+  The corners of the edges of the solid `cell` which face `origin` and are
+  not covered by a neighbouring solid cell, as offsets in blocks from the
+  top left corner. Only these corners can be on the outline of a shadow, so
+  cells hidden behind other cells have none
+*/
+fn facing_corners<L: BlockLookup>(grid: &L, cell: na::Vector2<i32>, origin: na::Vector2<f32>)
+    -> Vec<(f32, f32)>
+{
+    let min = grid::cell_to_world(cell);
+    let max = min + na::Vector2::new(BLOCK_SIZE, BLOCK_SIZE);
+
+    // The side of the cell, whether the light is in front of it and the
+    // corners at the ends of it
+    let edges = [
+        (na::Vector2::new(-1, 0), origin.x < min.x, [(0., 0.), (0., 1.)]),
+        (na::Vector2::new(1, 0), origin.x > max.x, [(1., 0.), (1., 1.)]),
+        (na::Vector2::new(0, -1), origin.y < min.y, [(0., 0.), (1., 0.)]),
+        (na::Vector2::new(0, 1), origin.y > max.y, [(0., 1.), (1., 1.)]),
+    ];
+
+    let mut corners = vec!();
+    for &(side, faces_light, ref ends) in &edges
+    {
+        if !faces_light || raycast::blocks_sight(grid, &(cell + side))
+        {
+            continue;
+        }
+
+        for end in ends
+        {
+            if !corners.contains(end)
+            {
+                corners.push(*end);
+            }
+        }
+    }
+    corners
+}
+
+/**
+  Returns the outline of the area visible from `origin` within `radius`,
+  ordered by angle around `origin`. Drawing a triangle fan from `origin`
+  through the points covers everything that the light reaches
+*/
+pub fn visibility_polygon<L: BlockLookup>(
+            grid: &L,
+            origin: na::Vector2<f32>,
+            radius: f32
+        ) -> Vec<na::Vector2<f32>>
+{
+    if radius <= 0. || raycast::blocks_sight(grid, &grid::world_to_cell(origin))
+    {
+        return vec!();
+    }
+
+    let mut angles = (0..CIRCLE_RAYS)
+        .map(|i| i as f32 / CIRCLE_RAYS as f32 * 2. * PI)
+        .collect::<Vec<_>>();
+
+    let min = grid::world_to_cell(origin - na::Vector2::new(radius, radius));
+    let max = grid::world_to_cell(origin + na::Vector2::new(radius, radius));
+    for y in min.y..max.y + 1
+    {
+        for x in min.x..max.x + 1
+        {
+            let cell = na::Vector2::new(x, y);
+            if !raycast::blocks_sight(grid, &cell)
+            {
+                continue;
+            }
+
+            let corner = grid::cell_to_world(cell);
+            for &(dx, dy) in &facing_corners(grid, cell, origin)
+            {
+                let offset = corner + na::Vector2::new(dx, dy) * BLOCK_SIZE - origin;
+                let angle = offset.y.atan2(offset.x);
+
+                for &angle in &[angle - CORNER_OFFSET, angle, angle + CORNER_OFFSET]
+                {
+                    // Keep all angles in the same range as the circle rays
+                    // so that sorting them goes around the light once
+                    angles.push(if angle < 0. { angle + 2. * PI } else { angle });
+                }
+            }
+        }
+    }
+
+    angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    angles.into_iter()
+        .map(|angle| {
+            let direction = na::Vector2::new(angle.cos(), angle.sin());
+            match raycast::raycast(grid, origin, direction, radius)
+            {
+                Some(hit) => hit.point,
+                None => origin + direction * radius
+            }
+        })
+        .collect()
+}
+
+
+/**
+  Returns the colour of the light reaching `point` from `lights`. Blocks
+  occlude the lights in the same way as in the light map, and soft shadows
+  use the same sample positions so partly hidden points get partial light
+*/
+pub fn light_color_at<L: BlockLookup>(
+            lights: &[PointLight],
+            grid: &L,
+            point: na::Vector2<f32>
+        ) -> (f32, f32, f32)
+{
+    let mut result = (0., 0., 0.);
+
+    for light in lights
+    {
+        let positions = light.sample_positions();
+        let intensity = light.intensity / positions.len() as f32;
+
+        for position in positions
+        {
+            let offset = point - position;
+            let distance = (offset.x * offset.x + offset.y * offset.y).sqrt();
+            if distance >= light.radius || !raycast::has_line_of_sight(grid, position, point)
+            {
+                continue;
+            }
+
+            let amount = intensity * attenuation(distance, light.radius);
+            result.0 += light.color.0 * amount;
+            result.1 += light.color.1 * amount;
+            result.2 += light.color.2 * amount;
+        }
+    }
+
+    result
+}
+
+/**
+  Returns how brightly `point` is lit, ignoring the colour of the lights.
+  0 is complete darkness and 1 is right next to a light of intensity 1
+*/
+pub fn light_level_at<L: BlockLookup>(
+            lights: &[PointLight],
+            grid: &L,
+            point: na::Vector2<f32>
+        ) -> f32
+{
+    let (r, g, b) = light_color_at(lights, grid, point);
+    r.max(g).max(b)
+}
+
+
+#[derive(Copy, Clone)]
+pub struct LightVertex
+{
+    pub position: (f32, f32)
+}
+implement_vertex!(LightVertex, position);
+
+
+/// Vertices the light vertex buffer has room for when it is created
+const INITIAL_VERTEX_CAPACITY: usize = 1024;
+
+pub struct LightRenderer
+{
+    context: Rc<Context>,
+    shader: glium::Program,
+    /// Reused for the triangles of every light. Only the start of it is
+    /// drawn when a light needs fewer vertices than it has room for
+    vertices: RefCell<glium::VertexBuffer<LightVertex>>,
+}
+
+impl LightRenderer
+{
+    pub fn new(facade: &Facade) -> LightRenderer
+    {
+        let program = glium::Program::from_source(
+                    facade,
+                    LIGHT_VERTEX_SHADER,
+                    LIGHT_FRAGMENT_SHADER,
+                    None
+                ).unwrap();
+
+        LightRenderer {
+            context: facade.get_context().clone(),
+            shader: program,
+            vertices: RefCell::new(
+                glium::VertexBuffer::empty_dynamic(facade, INITIAL_VERTEX_CAPACITY).unwrap()
+            ),
+        }
+    }
+
+    /**
+      Draws `lights` into `target` which should be the light map
+    */
+    pub fn draw<L: BlockLookup>(
+                &self,
+                target: &mut SimpleFrameBuffer,
+                lights: &[PointLight],
+                grid: &L,
+                camera_state: &CameraState
+            )
+    {
+        let (target_width, target_height) = target.get_dimensions();
+        let matrix = camera_state.get_matrix((target_width, target_height))
+            * drawing_util::get_window_scaling_matrix((target_width as f32, target_height as f32));
+        let matrix_data: [[f32;4]; 4] = *matrix.as_ref();
+
+        let params = glium::draw_parameters::DrawParameters {
+            blend: glium::draw_parameters::Blend {
+                color: glium::draw_parameters::BlendingFunction::Addition {
+                    source: glium::draw_parameters::LinearBlendingFactor::One,
+                    destination: glium::draw_parameters::LinearBlendingFactor::One
+                },
+                alpha: glium::draw_parameters::BlendingFunction::Addition {
+                    source: glium::draw_parameters::LinearBlendingFactor::One,
+                    destination: glium::draw_parameters::LinearBlendingFactor::One
+                },
+                constant_value: (0., 0., 0., 0.)
+            },
+            .. Default::default()
+        };
+
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+
+        for light in lights
+        {
+            let positions = light.sample_positions();
+            let intensity = light.intensity / positions.len() as f32;
+
+            for position in positions
+            {
+                let polygon = visibility_polygon(grid, position, light.radius);
+                if polygon.len() < 2
+                {
+                    continue;
+                }
+
+                // A triangle fan around the light, closed at the end
+                let mut shape = vec!();
+                for i in 0..polygon.len()
+                {
+                    let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+                    shape.push(LightVertex { position: (position.x, position.y) });
+                    shape.push(LightVertex { position: (a.x, a.y) });
+                    shape.push(LightVertex { position: (b.x, b.y) });
+                }
+
+                let mut vertices = self.vertices.borrow_mut();
+                if vertices.len() < shape.len()
+                {
+                    let capacity = shape.len().next_power_of_two();
+                    *vertices = glium::VertexBuffer::empty_dynamic(&self.context, capacity).unwrap();
+                }
+                let vertices = vertices.slice(0..shape.len()).unwrap();
+                vertices.write(&shape);
+
+                let uniforms = uniform! {
+                    matrix: matrix_data,
+                    light_position: (position.x, position.y),
+                    radius: light.radius,
+                    light_color: light.color,
+                    intensity: intensity
+                };
+
+                target.draw(vertices, &indices, &self.shader, &uniforms, &params).unwrap();
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+    use grid::BlockType;
+    use std::collections::HashMap;
+
+    fn distance(a: na::Vector2<f32>, b: na::Vector2<f32>) -> f32
+    {
+        let offset = a - b;
+        (offset.x * offset.x + offset.y * offset.y).sqrt()
+    }
+
+    #[test]
+    fn attenuation_falls_off_to_zero_at_the_radius()
+    {
+        assert_eq!(attenuation(0., 100.), 1.);
+        assert_eq!(attenuation(50., 100.), 0.25);
+        assert_eq!(attenuation(100., 100.), 0.);
+        assert_eq!(attenuation(150., 100.), 0.);
+    }
+
+    #[test]
+    fn unblocked_lights_are_circles()
+    {
+        let grid = HashMap::<na::Vector2<i32>, BlockType>::new();
+        let origin = na::Vector2::new(10., 10.);
+
+        let polygon = visibility_polygon(&grid, origin, 100.);
+
+        assert_eq!(polygon.len(), CIRCLE_RAYS);
+        assert!(polygon.iter().all(|point| (distance(*point, origin) - 100.).abs() < 0.01));
+    }
+
+    #[test]
+    fn blocks_cast_shadows()
+    {
+        let mut grid = HashMap::new();
+        grid.insert(na::Vector2::new(2, 0), BlockType::Stone);
+        let origin = na::Vector2::new(16., 16.);
+
+        let polygon = visibility_polygon(&grid, origin, 200.);
+
+        // Straight to the right the light is stopped by the block
+        let right = polygon.iter()
+            .filter(|point| (point.y - 16.).abs() < 0.01 && point.x > 16.)
+            .next()
+            .unwrap();
+        assert_eq!(right.x, 64.);
+        // But it reaches its full radius in other directions
+        assert!(polygon.iter().any(|point| (distance(*point, origin) - 200.).abs() < 0.01));
+    }
+
+    #[test]
+    fn only_corners_of_edges_facing_the_light_are_used()
+    {
+        let mut grid = HashMap::new();
+        grid.insert(na::Vector2::new(2, 0), BlockType::Stone);
+        grid.insert(na::Vector2::new(3, 0), BlockType::Stone);
+        let origin = na::Vector2::new(16., 16.);
+
+        assert_eq!(facing_corners(&grid, na::Vector2::new(2, 0), origin), vec!((0., 0.), (0., 1.)));
+        // Hidden behind the first block
+        assert_eq!(facing_corners(&grid, na::Vector2::new(3, 0), origin), vec!());
+
+        let above = na::Vector2::new(80., -100.);
+        assert_eq!(
+            facing_corners(&grid, na::Vector2::new(2, 0), above),
+            vec!((0., 0.), (1., 0.))
+        );
+    }
+
+    #[test]
+    fn light_levels_fall_off_with_distance()
+    {
+        let grid = HashMap::<na::Vector2<i32>, BlockType>::new();
+        let lights = [PointLight::new(na::Vector2::new(0., 0.), 100.)];
+
+        assert_eq!(light_level_at(&lights, &grid, na::Vector2::new(0., 50.)), 0.25);
+        assert_eq!(light_level_at(&lights, &grid, na::Vector2::new(100., 0.)), 0.);
+
+        let red = [PointLight::new(na::Vector2::new(0., 0.), 100.)
+            .with_color((1., 0., 0.))
+            .with_intensity(2.)];
+        assert_eq!(light_color_at(&red, &grid, na::Vector2::new(50., 0.)), (0.5, 0., 0.));
+    }
+
+    #[test]
+    fn blocks_hide_points_from_lights()
+    {
+        let mut grid = HashMap::new();
+        grid.insert(na::Vector2::new(2, 0), BlockType::Stone);
+        let lights = [
+            PointLight::new(na::Vector2::new(16., 16.), 200.),
+            PointLight::new(na::Vector2::new(16., 16.), 200.).with_soft_shadows(8., 16)
+        ];
+
+        // Behind the wall
+        assert_eq!(light_level_at(&lights[..1], &grid, na::Vector2::new(112., 16.)), 0.);
+        assert_eq!(light_level_at(&lights[1..], &grid, na::Vector2::new(112., 16.)), 0.);
+        // Next to the wall
+        assert!(light_level_at(&lights[..1], &grid, na::Vector2::new(16., 80.)) > 0.);
+    }
+
+    #[test]
+    fn soft_shadows_partly_light_the_penumbra()
+    {
+        let mut grid = HashMap::new();
+        grid.insert(na::Vector2::new(2, 0), BlockType::Stone);
+        let soft = [PointLight::new(na::Vector2::new(16., 16.), 400.).with_soft_shadows(12., 32)];
+        let hard = [PointLight::new(na::Vector2::new(16., 16.), 400.)];
+
+        // Just inside the hard shadow of the bottom left corner of the block
+        let point = na::Vector2::new(300., 105.);
+        let level = light_level_at(&soft, &grid, point);
+
+        assert_eq!(light_level_at(&hard, &grid, point), 0.);
+        assert!(level > 0.);
+        assert!(level < light_level_at(&soft, &HashMap::new(), point));
+    }
+
+    #[test]
+    fn soft_shadow_samples_are_on_the_light_source()
+    {
+        let light = PointLight::new(na::Vector2::new(5., 5.), 100.)
+            .with_soft_shadows(4., 8);
+
+        let samples = light.sample_positions();
+
+        assert_eq!(samples.len(), 8);
+        assert!(samples.iter().all(|sample| distance(*sample, light.position) <= 4.));
+        assert_eq!(PointLight::new(na::zero(), 1.).sample_positions(), vec!(na::zero()));
+    }
+}
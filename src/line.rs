@@ -4,6 +4,7 @@ use camera_state::CameraState;
 
 use glium;
 use glium::Surface;
+use glium::backend::Facade;
 use glium::framebuffer::SimpleFrameBuffer;
 
 use glium_types::{Vertex};
@@ -42,7 +43,7 @@ pub struct Line
 
 impl Line
 {
-    pub fn new(display: &glium::Display, start: na::Vector2<f32>, end: na::Vector2<f32>) -> Line
+    pub fn new(display: &Facade, start: na::Vector2<f32>, end: na::Vector2<f32>) -> Line
     {
         let shape = vec!(
                 //First triangle
@@ -84,7 +85,7 @@ impl Drawable for Line
         {
             let (target_width, target_height) = target.get_dimensions();
 
-            let world_matrix = camera_state.get_matrix()
+            let world_matrix = camera_state.get_matrix((target_width, target_height))
                 * drawing_util::get_window_scaling_matrix((target_width as f32, target_height as f32));
 
             let final_matrix = world_matrix;
@@ -6,6 +6,8 @@ extern crate nalgebra as na;
 extern crate image;
 extern crate time;
 extern crate rand;
+extern crate serde_json;
+extern crate xml;
 
 #[macro_use]
 extern crate glium;
@@ -16,13 +18,36 @@ extern crate lazy_static;
 mod drawable;
 mod drawing_util;
 mod camera_state;
+mod camera_controller;
+mod camera_shake;
+mod coordinates;
+mod culling;
 mod constants;
 mod sprite;
+mod atlas;
+mod sprite_batch;
+mod layer;
+mod parallax;
+mod animation;
+mod spritesheet;
 mod glium_types;
 mod line;
 mod rendering;
 mod render_steps;
+mod render_graph;
+mod headless;
+mod capture;
 mod grid;
+mod grid_renderer;
+mod level;
+mod prefab;
+mod collision;
+mod character;
+mod raycast;
+mod pathfinding;
+mod lighting;
+mod bloom;
+mod tiled;
 
 use drawable::{Drawable};
 use sprite::{SpriteFactory};
@@ -40,18 +65,15 @@ use glium_types::{Pixel};
 
 use std::path::Path;
 
-use rendering::RenderProcess;
-use render_steps::{RenderSteps, RenderParameters};
+use rendering::{RenderProcess, RenderStep};
+use render_steps::{RenderSteps, RenderParameters, PostProcessSettings};
 
 use std::collections::HashMap;
 
 
 pub fn load_texture<'a>(filename: &Path) -> RawImage2d<'a, u8> {
-    let image = image::open(filename).unwrap().to_rgba();
-    let dimensions = image.dimensions();
-    let raw_pixels = image.into_raw();
-
-    RawImage2d::from_raw_rgba(raw_pixels, dimensions)
+    drawing_util::try_load_texture(filename)
+        .unwrap_or_else(|e| panic!("Failed to load texture {}: {}", filename.display(), e))
 }
 
 
@@ -61,15 +83,20 @@ fn load_textures(display: &glium::Display)
 {
     let mut result = HashMap::new();
 
-    fn add_texture(display: &glium::Display, map: &mut HashMap<grid::BlockType, Vec<Arc<SrgbTexture2d>>>, path: &str) {
-        map.insert(
-            grid::BlockType::Stone,
-            vec!(Arc::new(SrgbTexture2d::new(display, load_texture(Path::new(path))).unwrap()))
+    fn add_texture(
+            display: &glium::Display,
+            map: &mut HashMap<grid::BlockType, Vec<Arc<SrgbTexture2d>>>,
+            block_type: grid::BlockType,
+            path: &str
+        )
+    {
+        map.entry(block_type).or_insert_with(Vec::new).push(
+            Arc::new(SrgbTexture2d::new(display, load_texture(Path::new(path))).unwrap())
         );
     };
 
-    add_texture(display, &mut result, "media/stone.png");
-    add_texture(display, &mut result, "media/StoneLadder.png");
+    add_texture(display, &mut result, grid::BlockType::Stone, "media/stone.png");
+    add_texture(display, &mut result, grid::BlockType::StoneLadder, "media/StoneLadder.png");
     result
 }
 
@@ -105,8 +132,16 @@ pub fn run_selector() {
             render_steps::DEFAULT_FRAGMENT_SHADER,
             render_steps::default_render_function
         );
+    // Nothing in the demo is lit so everything gets full ambient light
+    render_process.uniforms().set_settings(PostProcessSettings {
+        ambient: (1., 1., 1.),
+        .. Default::default()
+    });
 
     let mut t: f32 = 0.;
+    let mut take_screenshot = false;
+    let mut toggle_recording = false;
+    let mut recording: Option<capture::FrameSequence> = None;
 
 
     let mut render_targets = render_process.get_targets();
@@ -134,11 +169,66 @@ pub fn run_selector() {
 
         target.finish().unwrap();
 
+        if take_screenshot {
+            take_screenshot = false;
+
+            let frame = capture::capture_frame(&display);
+            let name = format!("screenshot_{}", time::get_time().sec);
+            let saved = capture::save_screenshot(
+                    Path::new("screenshots"),
+                    &name,
+                    &frame,
+                    &render_process.capture_steps()
+                );
+            if let Err(e) = saved {
+                eprintln!("Failed to save screenshot: {}", e);
+            }
+        }
+
+        if toggle_recording {
+            toggle_recording = false;
+
+            recording = match recording.take() {
+                Some(_) => None,
+                None => {
+                    let directory = Path::new("recordings").join(format!("{}", time::get_time().sec));
+                    match capture::FrameSequence::new(&directory, "frame") {
+                        Ok(sequence) => Some(sequence),
+                        Err(e) => {
+                            eprintln!("Failed to start recording: {}", e);
+                            None
+                        }
+                    }
+                }
+            };
+        }
+
+        let mut recording_failed = false;
+        if let Some(ref mut sequence) = recording {
+            if let Err(e) = sequence.record(&capture::capture_frame(&display)) {
+                eprintln!("Failed to record frame, stopping the recording: {}", e);
+                recording_failed = true;
+            }
+        }
+        if recording_failed {
+            recording = None;
+        }
+
         events_loop.poll_events(|ev| {
             match ev {
                 glium::glutin::Event::WindowEvent{window_id, event} => {
                     match event {
                         glium::glutin::WindowEvent::Closed => return,
+                        glium::glutin::WindowEvent::KeyboardInput{input, ..} => {
+                            if input.state == glium::glutin::ElementState::Pressed {
+                                match input.virtual_keycode {
+                                    Some(glium::glutin::VirtualKeyCode::F12) => take_screenshot = true,
+                                    // Starts or stops saving every frame
+                                    Some(glium::glutin::VirtualKeyCode::F11) => toggle_recording = true,
+                                    _ => {}
+                                }
+                            }
+                        }
                         _ => ()
                     }
                 }
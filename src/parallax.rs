@@ -0,0 +1,289 @@
+/*!
+  Background layers which scroll slower than the rest of the world to look
+  further away.
+
+  A layer is drawn as a quad covering the whole target where the texture
+  coordinates are found from the position on the target. The layer sees the
+  world through a camera that has moved `scroll_factor` times as far as the
+  real camera, so a factor of 0 stays fixed on the screen and a factor of 1
+  moves with the world.
+*/
+extern crate nalgebra as na;
+
+use glium;
+use glium::Surface;
+use glium::backend::Facade;
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::SrgbTexture2d;
+use glium::uniforms::{MagnifySamplerFilter, SamplerWrapFunction};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use camera_state::CameraState;
+use coordinates;
+use drawable::Drawable;
+use glium_types::Vertex;
+use render_steps::RenderSteps;
+use rendering;
+
+
+pub const PARALLAX_VERTEX_SHADER: &'static str = r#"
+        #version 140
+        in vec2 position;
+        in vec2 tex_coords;
+        out vec2 v_tex_coords;
+        // Moves clip space positions to texture coordinates
+        uniform mat3 uv_transform;
+        void main() {
+            v_tex_coords = (uv_transform * vec3(position, 1.0)).xy;
+            gl_Position = vec4(position, 0.0, 1.0);
+        }
+    "#;
+pub const PARALLAX_FRAGMENT_SHADER: &'static str = r#"
+        #version 140
+        in vec2 v_tex_coords;
+        out vec4 color;
+        uniform sampler2D tex;
+        // 1 for the axes that the texture is repeated along
+        uniform vec2 repeat;
+        void main() {
+            bool outside_x = v_tex_coords.x < 0. || v_tex_coords.x > 1.;
+            bool outside_y = v_tex_coords.y < 0. || v_tex_coords.y > 1.;
+            if((outside_x && repeat.x == 0.) || (outside_y && repeat.y == 0.))
+            {
+                discard;
+            }
+            color = texture(tex, v_tex_coords);
+        }
+    "#;
+
+
+/**
+  Returns the column major matrix which moves positions of the target in
+  clip space to texture coordinates of a parallax layer.
+
+  `offset` is the world position of the top left corner of the texture and
+  `texture_size` is the size of the texture in world pixels
+*/
+pub fn uv_transform(
+            camera_state: &CameraState,
+            scroll_factor: na::Vector2<f32>,
+            offset: na::Vector2<f32>,
+            texture_size: na::Vector2<f32>,
+            target_size: (u32, u32)
+        ) -> [[f32; 3]; 3]
+{
+    let position = camera_state.get_position();
+    let mut layer_camera = camera_state.clone();
+    layer_camera.set_position(na::Vector2::new(position.x * scroll_factor.x, position.y * scroll_factor.y));
+
+    let to_uv = |clip: na::Vector2<f32>| {
+        let world = coordinates::clip_to_world(&layer_camera, target_size, clip) - offset;
+        na::Vector2::new(world.x / texture_size.x, world.y / texture_size.y)
+    };
+
+    // The texture coordinates at the middle of the target and how they
+    // change along the clip space axes. Rotating cameras make both axes
+    // change both coordinates
+    let middle = to_uv(na::Vector2::new(0., 0.));
+    let x_axis = to_uv(na::Vector2::new(1., 0.)) - middle;
+    let y_axis = to_uv(na::Vector2::new(0., 1.)) - middle;
+
+    [
+        [x_axis.x, x_axis.y, 0.],
+        [y_axis.x, y_axis.y, 0.],
+        [middle.x, middle.y, 1.],
+    ]
+}
+
+
+pub struct ParallaxLayer
+{
+    textures: HashMap<RenderSteps, Arc<SrgbTexture2d>>,
+    texture_size: (u32, u32),
+
+    pub scroll_factor: na::Vector2<f32>,
+    /// World position of the top left corner of the texture
+    pub offset: na::Vector2<f32>,
+    /// Size of a texture pixel in world pixels
+    pub scale: f32,
+    pub repeat_x: bool,
+    pub repeat_y: bool,
+
+    vertices: glium::VertexBuffer<Vertex>,
+    shader: glium::Program,
+}
+
+impl ParallaxLayer
+{
+    pub fn new(facade: &Facade, texture: Arc<SrgbTexture2d>, scroll_factor: na::Vector2<f32>) -> ParallaxLayer
+    {
+        let texture_size = (texture.get_width(), texture.get_height().unwrap());
+
+        let mut textures = HashMap::new();
+        textures.insert(RenderSteps::Diffuse, texture);
+
+        let program = glium::Program::from_source(
+                    facade,
+                    PARALLAX_VERTEX_SHADER,
+                    PARALLAX_FRAGMENT_SHADER,
+                    None
+                ).unwrap();
+
+        ParallaxLayer {
+            textures: textures,
+            texture_size: texture_size,
+
+            scroll_factor: scroll_factor,
+            offset: na::zero(),
+            scale: 1.,
+            repeat_x: true,
+            repeat_y: false,
+
+            vertices: rendering::fullscreen_quad(facade),
+            shader: program,
+        }
+    }
+
+    /**
+      Sets the texture used in a step other than the diffuse step, for
+      example a glowing version of the layer for the emissive step. The
+      texture should have the same size as the diffuse texture
+    */
+    pub fn set_additional_texture(&mut self, step: RenderSteps, texture: Arc<SrgbTexture2d>)
+    {
+        self.textures.insert(step, texture);
+    }
+}
+
+impl Drawable for ParallaxLayer
+{
+    fn draw(&self, target: &mut SimpleFrameBuffer, step: &RenderSteps, camera_state: &CameraState)
+    {
+        let texture = match self.textures.get(step)
+        {
+            Some(texture) => texture,
+            None => return
+        };
+
+        let texture_size = na::Vector2::new(
+                self.texture_size.0 as f32 * self.scale,
+                self.texture_size.1 as f32 * self.scale
+            );
+
+        let uniforms = uniform! {
+            uv_transform: uv_transform(
+                    camera_state,
+                    self.scroll_factor,
+                    self.offset,
+                    texture_size,
+                    target.get_dimensions()
+                ),
+            repeat: (
+                if self.repeat_x { 1. } else { 0. },
+                if self.repeat_y { 1. } else { 0. }
+            ),
+            tex: texture.sampled()
+                .wrap_function(SamplerWrapFunction::Repeat)
+                .magnify_filter(MagnifySamplerFilter::Nearest),
+        };
+
+        let draw_parameters = glium::DrawParameters{
+            blend: glium::draw_parameters::Blend::alpha_blending(),
+            .. Default::default()
+        };
+
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        target.draw(&self.vertices, &indices, &self.shader, &uniforms, &draw_parameters).unwrap();
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+    use drawing_util;
+
+    /**
+      Returns the texture coordinates that the world point `world` is drawn
+      with by a layer moving with the world
+    */
+    fn texture_coordinates_at(
+                camera_state: &CameraState,
+                scroll_factor: na::Vector2<f32>,
+                world: na::Vector2<f32>
+            ) -> na::Vector2<f32>
+    {
+        let target_size = (800, 600);
+        let matrix = camera_state.get_matrix(target_size)
+            * drawing_util::get_window_scaling_matrix((800., 600.));
+        let clip = matrix * na::Vector4::new(world.x, world.y, 0., 1.);
+
+        let transform = uv_transform(
+                camera_state,
+                scroll_factor,
+                na::Vector2::new(10., 20.),
+                na::Vector2::new(100., 50.),
+                target_size
+            );
+        apply_transform(transform, na::Vector2::new(clip.x, clip.y))
+    }
+
+    fn apply_transform(transform: [[f32; 3]; 3], clip: na::Vector2<f32>) -> na::Vector2<f32>
+    {
+        na::Vector2::new(
+            transform[0][0] * clip.x + transform[1][0] * clip.y + transform[2][0],
+            transform[0][1] * clip.x + transform[1][1] * clip.y + transform[2][1]
+        )
+    }
+
+    fn assert_close(a: na::Vector2<f32>, b: na::Vector2<f32>)
+    {
+        assert!((a.x - b.x).abs() < 0.001 && (a.y - b.y).abs() < 0.001, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn layers_with_a_scroll_factor_of_one_move_with_the_world()
+    {
+        let mut camera_state = CameraState::new();
+        camera_state.set_position(na::Vector2::new(0.3, -0.2));
+        camera_state.set_zoom(2.);
+        camera_state.set_angle(0.4);
+
+        let uv = texture_coordinates_at(&camera_state, na::Vector2::new(1., 1.), na::Vector2::new(60., 45.));
+
+        assert_close(uv, na::Vector2::new(0.5, 0.5));
+    }
+
+    #[test]
+    fn layers_with_a_scroll_factor_of_zero_stay_on_the_screen()
+    {
+        let camera_state = CameraState::new();
+        let mut moved = CameraState::new();
+        moved.set_position(na::Vector2::new(0.5, 0.25));
+
+        let factor = na::Vector2::new(0., 0.);
+        let before = texture_coordinates_at(&camera_state, factor, na::Vector2::new(60., 45.));
+
+        // The same point on the screen after the camera moved
+        let uv = {
+            let transform = uv_transform(
+                    &moved,
+                    factor,
+                    na::Vector2::new(10., 20.),
+                    na::Vector2::new(100., 50.),
+                    (800, 600)
+                );
+            let clip = camera_state.get_matrix((800, 600))
+                * drawing_util::get_window_scaling_matrix((800., 600.))
+                * na::Vector4::new(60., 45., 0., 1.);
+            apply_transform(transform, na::Vector2::new(clip.x, clip.y))
+        };
+
+        assert_close(uv, before);
+    }
+}
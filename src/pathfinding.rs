@@ -0,0 +1,445 @@
+/*!
+  Navigation graph and A* search for agents that walk, jump, drop and climb.
+
+  Agents are assumed to fit in a single cell. A cell is a node in the graph
+  if an agent can stand in it: it is empty and has something solid (or the
+  top of a ladder) below it, or it is a ladder.
+*/
+extern crate nalgebra as na;
+
+use grid::{BlockLookup, Grid};
+use collision;
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+
+const WALK_COST: u32 = 10;
+const CLIMB_COST: u32 = 15;
+const DROP_COST: u32 = 10;
+const FALL_COST_PER_CELL: u32 = 5;
+const JUMP_COST: u32 = 20;
+/// Lower bound on the cost of moving one cell in any direction, used by the
+/// A* heuristic
+const MIN_COST_PER_CELL: u32 = 5;
+
+
+/**
+  Movement capabilities of an agent, measured in cells
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NavParameters {
+    pub max_jump_height: i32,
+    pub max_jump_distance: i32,
+    pub max_drop_height: i32
+}
+
+impl Default for NavParameters {
+    fn default() -> Self {
+        Self {
+            max_jump_height: 2,
+            max_jump_distance: 3,
+            max_drop_height: 6
+        }
+    }
+}
+
+/**
+  A single step along a path. Each action moves the agent to the cell `to`
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MoveAction {
+    Walk { to: na::Vector2<i32> },
+    Climb { to: na::Vector2<i32> },
+    Jump { to: na::Vector2<i32> },
+    /// Walk off a ledge and fall down
+    Drop { to: na::Vector2<i32> }
+}
+
+impl MoveAction {
+    pub fn target(&self) -> na::Vector2<i32> {
+        match *self {
+            MoveAction::Walk{to}
+                | MoveAction::Climb{to}
+                | MoveAction::Jump{to}
+                | MoveAction::Drop{to} => to
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Edge {
+    action: MoveAction,
+    cost: u32
+}
+
+
+fn is_open<L: BlockLookup>(grid: &L, cell: &na::Vector2<i32>) -> bool {
+    !collision::is_solid(grid, cell)
+}
+
+fn is_standable<L: BlockLookup>(grid: &L, cell: &na::Vector2<i32>) -> bool {
+    let below = cell + na::Vector2::new(0, 1);
+
+    is_open(grid, cell) && (
+        collision::is_climbable(grid, cell)
+        || collision::is_solid(grid, &below)
+        || collision::is_platform(grid, &below)
+    )
+}
+
+fn manhattan_distance(a: &na::Vector2<i32>, b: &na::Vector2<i32>) -> u32 {
+    ((a.x - b.x).abs() + (a.y - b.y).abs()) as u32
+}
+
+
+pub struct NavGraph {
+    parameters: NavParameters,
+    nodes: HashMap<na::Vector2<i32>, Vec<Edge>>
+}
+
+impl NavGraph {
+    /**
+      Builds a graph for all cells between `min` and `max`, both inclusive
+    */
+    pub fn build<L: BlockLookup>(
+            grid: &L,
+            min: na::Vector2<i32>,
+            max: na::Vector2<i32>,
+            parameters: NavParameters
+        ) -> Self
+    {
+        let mut result = Self {
+            parameters,
+            nodes: HashMap::new()
+        };
+        result.rebuild_region(grid, min, max);
+        result
+    }
+
+    /**
+      Builds a graph covering all blocks in the grid and the cells right
+      around them
+    */
+    pub fn for_grid(grid: &Grid, parameters: NavParameters) -> Self {
+        let padding = na::Vector2::new(1, 1);
+
+        match grid.bounding_box() {
+            Some((min, max)) => Self::build(grid, min - padding, max + padding, parameters),
+            None => Self { parameters, nodes: HashMap::new() }
+        }
+    }
+
+    pub fn contains(&self, cell: &na::Vector2<i32>) -> bool {
+        self.nodes.contains_key(cell)
+    }
+
+    /**
+      Returns all actions that can be taken from a cell
+    */
+    pub fn actions_from(&self, cell: &na::Vector2<i32>) -> Vec<MoveAction> {
+        self.nodes.get(cell)
+            .map(|edges| edges.iter().map(|edge| edge.action).collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /**
+      Updates the graph after the blocks at `cells` have been added or
+      removed. Only the nodes whose moves could pass through the changed
+      cells are recomputed
+    */
+    pub fn update_cells<L: BlockLookup>(&mut self, grid: &L, cells: &[na::Vector2<i32>]) {
+        let reach = na::Vector2::new(
+                self.parameters.max_jump_distance + 1,
+                self.parameters.max_jump_height.max(self.parameters.max_drop_height) + 2
+            );
+
+        for cell in cells {
+            self.rebuild_region(grid, cell - reach, cell + reach);
+        }
+    }
+
+    fn rebuild_region<L: BlockLookup>(
+            &mut self,
+            grid: &L,
+            min: na::Vector2<i32>,
+            max: na::Vector2<i32>
+        )
+    {
+        for y in min.y..max.y + 1 {
+            for x in min.x..max.x + 1 {
+                let cell = na::Vector2::new(x, y);
+
+                if is_standable(grid, &cell) {
+                    let edges = self.edges_from(grid, &cell);
+                    self.nodes.insert(cell, edges);
+                }
+                else {
+                    self.nodes.remove(&cell);
+                }
+            }
+        }
+    }
+
+    fn edges_from<L: BlockLookup>(&self, grid: &L, from: &na::Vector2<i32>) -> Vec<Edge> {
+        let mut result = vec!();
+        let parameters = &self.parameters;
+
+        // Climbing up and down ladders, including onto the top of them
+        let above = from - na::Vector2::new(0, 1);
+        let below = from + na::Vector2::new(0, 1);
+        if collision::is_climbable(grid, from) && is_standable(grid, &above) {
+            result.push(Edge { action: MoveAction::Climb{to: above}, cost: CLIMB_COST });
+        }
+        if collision::is_climbable(grid, &below) {
+            result.push(Edge { action: MoveAction::Climb{to: below}, cost: CLIMB_COST });
+        }
+
+        for &direction in &[-1, 1] {
+            let side = from + na::Vector2::new(direction, 0);
+
+            if is_standable(grid, &side) {
+                result.push(Edge { action: MoveAction::Walk{to: side}, cost: WALK_COST });
+            }
+            else if is_open(grid, &side) {
+                for fall in 1..parameters.max_drop_height + 1 {
+                    let target = side + na::Vector2::new(0, fall);
+                    if !is_open(grid, &target) {
+                        break;
+                    }
+                    if is_standable(grid, &target) {
+                        result.push(Edge {
+                            action: MoveAction::Drop{to: target},
+                            cost: DROP_COST + FALL_COST_PER_CELL * fall as u32
+                        });
+                        break;
+                    }
+                }
+            }
+
+            for distance in 1..parameters.max_jump_distance + 1 {
+                for height in -parameters.max_jump_height..parameters.max_drop_height + 1 {
+                    // Covered by walking
+                    if distance == 1 && height == 0 {
+                        continue;
+                    }
+
+                    let target = from + na::Vector2::new(direction * distance, height);
+                    if is_standable(grid, &target) && self.jump_is_clear(grid, from, &target) {
+                        result.push(Edge {
+                            action: MoveAction::Jump{to: target},
+                            cost: JUMP_COST + WALK_COST * manhattan_distance(from, &target)
+                        });
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /**
+      Jumps go straight up to the height of the higher of the two cells,
+      across, and then straight down. All cells along the way must be open
+    */
+    fn jump_is_clear<L: BlockLookup>(
+            &self,
+            grid: &L,
+            from: &na::Vector2<i32>,
+            to: &na::Vector2<i32>
+        ) -> bool
+    {
+        let apex = from.y.min(to.y);
+        let direction = (to.x - from.x).signum();
+
+        let rising = (apex..from.y + 1).all(|y| is_open(grid, &na::Vector2::new(from.x, y)));
+        let falling = (apex..to.y + 1).all(|y| is_open(grid, &na::Vector2::new(to.x, y)));
+
+        let mut x = from.x;
+        let mut across = true;
+        while x != to.x {
+            x += direction;
+            across = across && is_open(grid, &na::Vector2::new(x, apex));
+        }
+
+        rising && across && falling
+    }
+
+    /**
+      Finds the cheapest sequence of actions which moves an agent from
+      `start` to `goal`. Returns None if either cell is not in the graph or
+      if the goal can't be reached
+    */
+    pub fn find_path(&self, start: na::Vector2<i32>, goal: na::Vector2<i32>) -> Option<Vec<MoveAction>> {
+        if !self.contains(&start) || !self.contains(&goal) {
+            return None;
+        }
+
+        let heuristic = |cell: &na::Vector2<i32>| MIN_COST_PER_CELL * manhattan_distance(cell, &goal);
+
+        let mut open = BinaryHeap::new();
+        let mut cost_so_far = HashMap::new();
+        let mut came_from: HashMap<na::Vector2<i32>, (na::Vector2<i32>, MoveAction)> = HashMap::new();
+
+        cost_so_far.insert(start, 0);
+        open.push(Reverse((heuristic(&start), start.x, start.y)));
+
+        while let Some(Reverse((_, x, y))) = open.pop() {
+            let current = na::Vector2::new(x, y);
+
+            if current == goal {
+                let mut path = vec!();
+                let mut cell = goal;
+                while let Some(&(previous, action)) = came_from.get(&cell) {
+                    path.push(action);
+                    cell = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let current_cost = cost_so_far[&current];
+            for edge in &self.nodes[&current] {
+                let next = edge.action.target();
+                if !self.contains(&next) {
+                    continue;
+                }
+
+                let new_cost = current_cost + edge.cost;
+                let is_better = cost_so_far.get(&next)
+                    .map(|&cost| new_cost < cost)
+                    .unwrap_or(true);
+
+                if is_better {
+                    cost_so_far.insert(next, new_cost);
+                    came_from.insert(next, (current, edge.action));
+                    open.push(Reverse((new_cost + heuristic(&next), next.x, next.y)));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+    use grid::BlockType;
+    use level;
+    use std::collections::HashMap;
+
+    fn grid_from(source: &str) -> HashMap<na::Vector2<i32>, BlockType> {
+        let source = format!("origin 0 0\nlegend\n# = Stone\nH = StoneLadder\nmap\n{}", source);
+
+        level::parse_level(&source)
+            .unwrap()
+            .into_iter()
+            .map(|(block_type, position)| (position, block_type))
+            .collect()
+    }
+
+    fn graph_for(grid: &HashMap<na::Vector2<i32>, BlockType>) -> NavGraph {
+        NavGraph::build(grid, na::Vector2::new(-1, -1), na::Vector2::new(12, 10), NavParameters::default())
+    }
+
+    #[test]
+    fn agents_walk_along_floors()
+    {
+        let grid = grid_from("\
+.....
+#####
+");
+        let path = graph_for(&grid).find_path(na::Vector2::new(0, 0), na::Vector2::new(3, 0)).unwrap();
+
+        assert_eq!(path, vec!(
+                MoveAction::Walk{to: na::Vector2::new(1, 0)},
+                MoveAction::Walk{to: na::Vector2::new(2, 0)},
+                MoveAction::Walk{to: na::Vector2::new(3, 0)},
+            ));
+    }
+
+    #[test]
+    fn agents_climb_ladders_to_high_floors()
+    {
+        let grid = grid_from("\
+......
+###H##
+...H..
+...H..
+...H..
+######
+");
+        let graph = graph_for(&grid);
+        let path = graph.find_path(na::Vector2::new(0, 4), na::Vector2::new(5, 0)).unwrap();
+
+        let climbs = path.iter()
+            .filter(|action| match **action { MoveAction::Climb{..} => true, _ => false })
+            .count();
+        assert_eq!(climbs, 4);
+        assert_eq!(path.last().unwrap().target(), na::Vector2::new(5, 0));
+
+        // And down again
+        assert!(graph.find_path(na::Vector2::new(5, 0), na::Vector2::new(0, 4)).is_some());
+    }
+
+    #[test]
+    fn agents_jump_over_gaps_and_drop_down()
+    {
+        let grid = grid_from("\
+..........
+###..####.
+........#.
+#########.
+");
+        let graph = graph_for(&grid);
+
+        let path = graph.find_path(na::Vector2::new(0, 0), na::Vector2::new(6, 0)).unwrap();
+        assert!(path.contains(&MoveAction::Jump{to: na::Vector2::new(5, 0)}));
+
+        assert_eq!(
+                graph.find_path(na::Vector2::new(5, 0), na::Vector2::new(4, 2)),
+                Some(vec!(MoveAction::Drop{to: na::Vector2::new(4, 2)}))
+            );
+
+        // Jumping back up out of the pit
+        assert!(graph.find_path(na::Vector2::new(7, 2), na::Vector2::new(0, 0)).is_some());
+
+        // There is nothing to land on to the right of the ledge
+        let drops_right = graph.actions_from(&na::Vector2::new(8, 0))
+            .iter()
+            .any(|action| match *action { MoveAction::Drop{..} => true, _ => false });
+        assert!(!drops_right);
+    }
+
+    #[test]
+    fn graph_updates_when_blocks_change()
+    {
+        let mut grid = grid_from("\
+.......
+#######
+");
+        let mut graph = graph_for(&grid);
+        let start = na::Vector2::new(0, 0);
+        let goal = na::Vector2::new(6, 0);
+        assert!(graph.find_path(start, goal).is_some());
+
+        // A wall too high to jump over
+        let wall = (-3..1).map(|y| na::Vector2::new(3, y)).collect::<Vec<_>>();
+        for cell in &wall {
+            grid.insert(*cell, BlockType::Stone);
+        }
+        graph.update_cells(&grid, &wall);
+        assert!(graph.find_path(start, goal).is_none());
+
+        for cell in &wall {
+            grid.remove(cell);
+        }
+        graph.update_cells(&grid, &wall);
+        assert!(graph.find_path(start, goal).is_some());
+    }
+}
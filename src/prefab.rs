@@ -0,0 +1,338 @@
+/*!
+  Named groups of blocks which can be transformed and stamped into a grid
+  any number of times.
+*/
+extern crate nalgebra as na;
+
+use grid::{self, BlockType};
+use layer::Layer;
+use level::{self, LevelLoadError};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+
+/**
+  What to do when a prefab is stamped on top of existing blocks
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictPolicy {
+    /// Replace the existing blocks
+    Overwrite,
+    /// Keep the existing blocks and only add the non overlapping ones
+    Skip,
+    /// Don't add anything and return the conflicting positions
+    Error
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PrefabConflict {
+    pub positions: Vec<na::Vector2<i32>>
+}
+
+impl fmt::Display for PrefabConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "prefab overlaps {} existing blocks", self.positions.len())
+    }
+}
+
+impl Error for PrefabConflict {
+    fn description(&self) -> &str {
+        "prefab overlaps existing blocks"
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prefab {
+    name: String,
+    blocks: Vec<(BlockType, na::Vector2<i32>)>,
+    /// The layer that stamped blocks are drawn on
+    layer: Layer
+}
+
+impl Prefab {
+    pub fn new(name: &str, blocks: Vec<(BlockType, na::Vector2<i32>)>) -> Self {
+        Self {
+            name: name.to_string(),
+            blocks,
+            layer: Layer::Tiles
+        }
+    }
+
+    pub fn with_layer(mut self, layer: Layer) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    /**
+      Loads a prefab from a file in the level format. The name of the prefab
+      is the file name without the extension
+    */
+    pub fn load(path: &Path) -> Result<Self, LevelLoadError> {
+        let blocks = level::load_level_file(path)?;
+
+        let name = path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+
+        Ok(Self::new(name, blocks))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn layer(&self) -> Layer {
+        self.layer
+    }
+
+    pub fn blocks(&self) -> &[(BlockType, na::Vector2<i32>)] {
+        &self.blocks
+    }
+
+    /**
+      Returns the smallest and largest corner of the cells covered by the
+      prefab, both inclusive. Empty prefabs have no bounding box
+    */
+    pub fn bounding_box(&self) -> Option<(na::Vector2<i32>, na::Vector2<i32>)> {
+        grid::cell_bounds(self.blocks.iter().map(|&(_, ref pos)| pos))
+    }
+
+    /**
+      Returns a copy of the prefab flipped around the vertical center of its
+      bounding box
+    */
+    pub fn mirrored_horizontally(&self) -> Prefab {
+        match self.bounding_box() {
+            Some((min, max)) => self.transformed(|pos| na::Vector2::new(min.x + max.x - pos.x, pos.y)),
+            None => self.clone()
+        }
+    }
+
+    /**
+      Returns a copy of the prefab rotated clockwise by the specified number
+      of quarter turns. The top left corner of the bounding box stays in place
+    */
+    pub fn rotated_clockwise(&self, quarter_turns: u32) -> Prefab {
+        let mut result = self.clone();
+        for _ in 0..quarter_turns % 4 {
+            // y points down, so a clockwise rotation moves +x to +y
+            let rotated = result.transformed(|pos| na::Vector2::new(-pos.y, pos.x));
+            result = rotated.moved_to_corner(self.bounding_box());
+        }
+        result
+    }
+
+    /**
+      Returns the blocks of the prefab moved by `offset`
+    */
+    pub fn placed_blocks(&self, offset: na::Vector2<i32>) -> Vec<(BlockType, na::Vector2<i32>)> {
+        self.blocks.iter()
+            .map(|&(block_type, pos)| (block_type, pos + offset))
+            .collect()
+    }
+
+    fn transformed<F>(&self, transform: F) -> Prefab
+        where F: Fn(na::Vector2<i32>) -> na::Vector2<i32>
+    {
+        Prefab {
+            name: self.name.clone(),
+            blocks: self.blocks.iter()
+                .map(|&(block_type, pos)| (block_type, transform(pos)))
+                .collect(),
+            layer: self.layer
+        }
+    }
+
+    fn moved_to_corner(self, target: Option<(na::Vector2<i32>, na::Vector2<i32>)>) -> Prefab {
+        match (self.bounding_box(), target) {
+            (Some((min, _)), Some((target_min, _))) => {
+                let offset = target_min - min;
+                Prefab {
+                    blocks: self.placed_blocks(offset),
+                    name: self.name,
+                    layer: self.layer
+                }
+            }
+            _ => self
+        }
+    }
+}
+
+
+/**
+  Decides which blocks of a placed prefab to add based on `policy`.
+  `is_occupied` should return true for positions that already have a block
+*/
+pub fn resolve_conflicts<F>(
+        blocks: Vec<(BlockType, na::Vector2<i32>)>,
+        policy: ConflictPolicy,
+        is_occupied: F
+    ) -> Result<Vec<(BlockType, na::Vector2<i32>)>, PrefabConflict>
+    where F: Fn(&na::Vector2<i32>) -> bool
+{
+    match policy {
+        ConflictPolicy::Overwrite => Ok(blocks),
+        ConflictPolicy::Skip => Ok(
+            blocks.into_iter()
+                .filter(|&(_, ref pos)| !is_occupied(pos))
+                .collect()
+        ),
+        ConflictPolicy::Error => {
+            let positions = blocks.iter()
+                .map(|&(_, pos)| pos)
+                .filter(|pos| is_occupied(pos))
+                .collect::<Vec<_>>();
+
+            if positions.is_empty() {
+                Ok(blocks)
+            }
+            else {
+                Err(PrefabConflict { positions })
+            }
+        }
+    }
+}
+
+
+pub struct PrefabLibrary {
+    prefabs: HashMap<String, Prefab>
+}
+
+impl PrefabLibrary {
+    pub fn new() -> Self {
+        Self {
+            prefabs: HashMap::new()
+        }
+    }
+
+    /**
+      Adds a prefab to the library, returning the previous prefab with the
+      same name if there was one
+    */
+    pub fn register(&mut self, prefab: Prefab) -> Option<Prefab> {
+        self.prefabs.insert(prefab.name.clone(), prefab)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.prefabs.keys().map(|name| &**name).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    pub fn load_file(&mut self, path: &Path) -> Result<(), LevelLoadError> {
+        let prefab = Prefab::load(path)?;
+        self.register(prefab);
+        Ok(())
+    }
+
+    /**
+      Loads all level files in a directory as prefabs
+    */
+    pub fn load_directory(&mut self, path: &Path) -> Result<(), LevelLoadError> {
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+
+            let is_level = path.extension()
+                .map(|extension| extension == level::FILE_EXTENSION)
+                .unwrap_or(false);
+
+            if path.is_file() && is_level {
+                self.load_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+
+    fn l_shape() -> Prefab {
+        Prefab::new("l", vec!(
+                (BlockType::Stone, na::Vector2::new(1, 1)),
+                (BlockType::Stone, na::Vector2::new(1, 2)),
+                (BlockType::StoneLadder, na::Vector2::new(2, 2)),
+            ))
+    }
+
+    #[test]
+    fn bounding_box_is_inclusive()
+    {
+        assert_eq!(
+                l_shape().bounding_box(),
+                Some((na::Vector2::new(1, 1), na::Vector2::new(2, 2)))
+            );
+        assert_eq!(Prefab::new("empty", vec!()).bounding_box(), None);
+    }
+
+    #[test]
+    fn mirroring_keeps_bounding_box()
+    {
+        let mirrored = l_shape().mirrored_horizontally();
+
+        assert_eq!(mirrored.blocks(), &[
+                (BlockType::Stone, na::Vector2::new(2, 1)),
+                (BlockType::Stone, na::Vector2::new(2, 2)),
+                (BlockType::StoneLadder, na::Vector2::new(1, 2)),
+            ]);
+        assert_eq!(mirrored.bounding_box(), l_shape().bounding_box());
+    }
+
+    #[test]
+    fn rotation_is_clockwise()
+    {
+        let rotated = l_shape().rotated_clockwise(1);
+
+        assert_eq!(rotated.blocks(), &[
+                (BlockType::Stone, na::Vector2::new(2, 1)),
+                (BlockType::Stone, na::Vector2::new(1, 1)),
+                (BlockType::StoneLadder, na::Vector2::new(1, 2)),
+            ]);
+        assert_eq!(l_shape().rotated_clockwise(4), l_shape());
+    }
+
+    #[test]
+    fn conflict_policies()
+    {
+        let blocks = l_shape().placed_blocks(na::Vector2::new(1, 0));
+        let occupied = |pos: &na::Vector2<i32>| *pos == na::Vector2::new(2, 1);
+
+        assert_eq!(
+                resolve_conflicts(blocks.clone(), ConflictPolicy::Overwrite, &occupied),
+                Ok(blocks.clone())
+            );
+        assert_eq!(
+                resolve_conflicts(blocks.clone(), ConflictPolicy::Skip, &occupied).unwrap().len(),
+                2
+            );
+        assert_eq!(
+                resolve_conflicts(blocks.clone(), ConflictPolicy::Error, &occupied),
+                Err(PrefabConflict { positions: vec!(na::Vector2::new(2, 1)) })
+            );
+    }
+
+    #[test]
+    fn library_replaces_prefabs_with_the_same_name()
+    {
+        let mut library = PrefabLibrary::new();
+
+        assert!(library.register(l_shape()).is_none());
+        assert!(library.register(l_shape().mirrored_horizontally()).is_some());
+        assert_eq!(library.get("l"), Some(&l_shape().mirrored_horizontally()));
+        assert_eq!(library.names(), vec!("l"));
+    }
+}
@@ -0,0 +1,293 @@
+/*!
+  Ray casting and line of sight queries against the grid.
+
+  All positions are in world pixels. Rays are stepped through the grid one
+  cell at a time (the DDA algorithm by Amanatides and Woo) so every cell the
+  ray passes through is checked exactly once. Only colliding blocks stop
+  rays, ladders can be seen through.
+*/
+extern crate nalgebra as na;
+
+use grid::{self, BlockLookup, BLOCK_SIZE};
+use collision;
+
+use std::collections::HashSet;
+use std::f32;
+
+
+/// Distance below which points are considered to be the same
+const EPSILON: f32 = 0.001;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// The cell that stopped the ray
+    pub cell: na::Vector2<i32>,
+    /// The point where the ray entered the cell
+    pub point: na::Vector2<f32>,
+    /// The normal of the side of the cell that was hit. This is zero if the
+    /// ray started inside the cell
+    pub normal: na::Vector2<f32>,
+    /// Distance from the origin of the ray to `point`
+    pub distance: f32
+}
+
+
+pub fn blocks_sight<L: BlockLookup>(grid: &L, cell: &na::Vector2<i32>) -> bool {
+    collision::is_solid(grid, cell)
+}
+
+fn length(vector: na::Vector2<f32>) -> f32 {
+    (vector.x * vector.x + vector.y * vector.y).sqrt()
+}
+
+/**
+  Returns the distance to the first cell boundary crossed along one axis and
+  the distance between consecutive boundaries
+*/
+fn initial_step(origin: f32, direction: f32, cell: i32) -> (f32, f32) {
+    if direction > 0. {
+        (((cell + 1) as f32 * BLOCK_SIZE - origin) / direction, BLOCK_SIZE / direction)
+    }
+    else if direction < 0. {
+        ((cell as f32 * BLOCK_SIZE - origin) / direction, -BLOCK_SIZE / direction)
+    }
+    else {
+        (f32::INFINITY, f32::INFINITY)
+    }
+}
+
+/**
+  Casts a ray from `origin` in `direction` and returns the first cell that
+  blocks it within `max_distance`. `direction` does not need to be
+  normalised. `max_distance` must be finite
+*/
+pub fn raycast<L: BlockLookup>(
+        grid: &L,
+        origin: na::Vector2<f32>,
+        direction: na::Vector2<f32>,
+        max_distance: f32
+    ) -> Option<RayHit>
+{
+    let mut cell = grid::world_to_cell(origin);
+
+    if blocks_sight(grid, &cell) {
+        return Some(RayHit {
+            cell,
+            point: origin,
+            normal: na::zero(),
+            distance: 0.
+        });
+    }
+
+    let direction_length = length(direction);
+    if direction_length == 0. || !max_distance.is_finite() {
+        return None;
+    }
+    let direction = direction / direction_length;
+
+    let step = na::Vector2::new(direction.x.signum() as i32, direction.y.signum() as i32);
+    let (mut next_x, delta_x) = initial_step(origin.x, direction.x, cell.x);
+    let (mut next_y, delta_y) = initial_step(origin.y, direction.y, cell.y);
+
+    loop {
+        let (distance, normal) = if next_x < next_y {
+            cell.x += step.x;
+            next_x += delta_x;
+            (next_x - delta_x, na::Vector2::new(-step.x as f32, 0.))
+        }
+        else {
+            cell.y += step.y;
+            next_y += delta_y;
+            (next_y - delta_y, na::Vector2::new(0., -step.y as f32))
+        };
+
+        if distance > max_distance {
+            return None;
+        }
+
+        if blocks_sight(grid, &cell) {
+            return Some(RayHit {
+                cell,
+                point: origin + direction * distance,
+                normal,
+                distance
+            });
+        }
+    }
+}
+
+/**
+  Returns true if no blocks are in the way between `from` and `to`
+*/
+pub fn has_line_of_sight<L: BlockLookup>(
+        grid: &L,
+        from: na::Vector2<f32>,
+        to: na::Vector2<f32>
+    ) -> bool
+{
+    let distance = length(to - from);
+
+    match raycast(grid, from, to - from, distance) {
+        Some(hit) => distance > EPSILON && hit.distance >= distance - EPSILON,
+        None => true
+    }
+}
+
+/**
+  Returns the cells whose centers are within `range` of `origin`, inside the
+  cone of `half_angle` radians around `direction` and visible from `origin`.
+  Blocking cells are included if the ray towards them hits them, so walls
+  facing the origin are visible
+*/
+pub fn visible_cells_in_cone<L: BlockLookup>(
+        grid: &L,
+        origin: na::Vector2<f32>,
+        direction: na::Vector2<f32>,
+        half_angle: f32,
+        range: f32
+    ) -> HashSet<na::Vector2<i32>>
+{
+    let mut result = HashSet::new();
+
+    let direction_length = length(direction);
+    if direction_length == 0. || range <= 0. {
+        return result;
+    }
+    let direction = direction / direction_length;
+    let min_cos = half_angle.cos();
+
+    let min = grid::world_to_cell(origin - na::Vector2::new(range, range));
+    let max = grid::world_to_cell(origin + na::Vector2::new(range, range));
+
+    for y in min.y..max.y + 1 {
+        for x in min.x..max.x + 1 {
+            let cell = na::Vector2::new(x, y);
+            let center = grid::cell_to_world(cell) + na::Vector2::new(BLOCK_SIZE, BLOCK_SIZE) * 0.5;
+
+            let offset = center - origin;
+            let distance = length(offset);
+            if distance > range {
+                continue;
+            }
+
+            // The cell containing the origin is always in view
+            let in_cone = distance < EPSILON
+                || (offset.x * direction.x + offset.y * direction.y) / distance >= min_cos;
+            if !in_cone {
+                continue;
+            }
+
+            let visible = match raycast(grid, origin, offset, distance) {
+                Some(hit) => hit.cell == cell,
+                None => true
+            };
+            if visible {
+                result.insert(cell);
+            }
+        }
+    }
+
+    result
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+    use grid::BlockType;
+    use std::collections::HashMap;
+    use std::f32::consts::PI;
+
+    /*
+      A wall in column 3 between rows -1 and 1 and a ladder at (1, 2)
+    */
+    fn wall_grid() -> HashMap<na::Vector2<i32>, BlockType> {
+        let mut grid = HashMap::new();
+        for y in -1..2 {
+            grid.insert(na::Vector2::new(3, y), BlockType::Stone);
+        }
+        grid.insert(na::Vector2::new(1, 2), BlockType::StoneLadder);
+        grid
+    }
+
+    #[test]
+    fn rays_hit_the_first_blocking_cell()
+    {
+        let hit = raycast(&wall_grid(), na::Vector2::new(16., 16.), na::Vector2::new(1., 0.), 1000.)
+            .unwrap();
+
+        assert_eq!(hit.cell, na::Vector2::new(3, 0));
+        assert_eq!(hit.point, na::Vector2::new(96., 16.));
+        assert_eq!(hit.normal, na::Vector2::new(-1., 0.));
+        assert_eq!(hit.distance, 80.);
+    }
+
+    #[test]
+    fn diagonal_rays_report_the_side_they_hit()
+    {
+        let hit = raycast(&wall_grid(), na::Vector2::new(16., 48.), na::Vector2::new(2., -1.), 1000.)
+            .unwrap();
+
+        assert_eq!(hit.cell, na::Vector2::new(3, 0));
+        assert_eq!(hit.normal, na::Vector2::new(-1., 0.));
+        assert!((hit.point.x - 96.).abs() < EPSILON);
+        assert!((hit.point.y - 8.).abs() < EPSILON);
+    }
+
+    #[test]
+    fn rays_stop_at_max_distance()
+    {
+        let grid = wall_grid();
+
+        assert_eq!(raycast(&grid, na::Vector2::new(16., 16.), na::Vector2::new(1., 0.), 79.), None);
+        assert_eq!(raycast(&grid, na::Vector2::new(16., 16.), na::Vector2::new(-1., 0.), 1000.), None);
+    }
+
+    #[test]
+    fn rays_starting_in_blocks_hit_immediately()
+    {
+        let hit = raycast(&wall_grid(), na::Vector2::new(100., 5.), na::Vector2::new(1., 0.), 1000.)
+            .unwrap();
+
+        assert_eq!(hit.cell, na::Vector2::new(3, 0));
+        assert_eq!(hit.distance, 0.);
+        assert_eq!(hit.normal, na::zero());
+    }
+
+    #[test]
+    fn line_of_sight()
+    {
+        let grid = wall_grid();
+
+        assert!(!has_line_of_sight(&grid, na::Vector2::new(16., 16.), na::Vector2::new(200., 16.)));
+        assert!(has_line_of_sight(&grid, na::Vector2::new(16., 16.), na::Vector2::new(90., 16.)));
+        assert!(has_line_of_sight(&grid, na::Vector2::new(16., 16.), na::Vector2::new(200., 200.)));
+        // Ladders don't block sight
+        assert!(has_line_of_sight(&grid, na::Vector2::new(16., 80.), na::Vector2::new(80., 80.)));
+    }
+
+    #[test]
+    fn cones_only_contain_visible_cells_in_front()
+    {
+        let cells = visible_cells_in_cone(
+                &wall_grid(),
+                na::Vector2::new(16., 16.),
+                na::Vector2::new(1., 0.),
+                PI / 8.,
+                200.
+            );
+
+        assert!(cells.contains(&na::Vector2::new(0, 0)));
+        assert!(cells.contains(&na::Vector2::new(2, 0)));
+        // The wall itself is seen but not what is behind it
+        assert!(cells.contains(&na::Vector2::new(3, 0)));
+        assert!(!cells.contains(&na::Vector2::new(4, 0)));
+        // Behind the origin and outside the cone
+        assert!(!cells.contains(&na::Vector2::new(-1, 0)));
+        assert!(!cells.contains(&na::Vector2::new(1, 3)));
+    }
+}
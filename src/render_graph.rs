@@ -0,0 +1,557 @@
+/*!
+  A render graph of full screen passes.
+
+  Every pass is a fragment shader which reads a set of named textures and
+  writes to a set of named textures. The graph allocates the textures written
+  by the passes and runs the passes in an order where every texture is
+  written before it is read. Textures that are not written by any pass, like
+  the render step targets, are passed to `RenderGraph::draw` by the caller.
+
+  The outputs of a pass are the `out` variables of its fragment shader and
+  its inputs are `sampler2D` uniforms with the same names. A pass that writes
+  to `SCREEN_TARGET` draws to the surface given to `draw` and can not write
+  to any other texture.
+
+  A `RenderProcess` can run a graph instead of its single pass, see
+  `RenderProcess::set_graph`.
+*/
+use glium::texture::texture2d::Texture2d;
+use glium::texture::{MipmapsOption, UncompressedFloatFormat};
+use glium::backend::{Context, Facade};
+use glium::framebuffer::MultiOutputFrameBuffer;
+use glium::uniforms::{
+    MagnifySamplerFilter,
+    MinifySamplerFilter,
+    SamplerBehavior,
+    SamplerWrapFunction,
+    UniformValue,
+    Uniforms
+};
+use glium::{Program, Surface, VertexBuffer};
+use glium;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+use glium_types::Vertex;
+use rendering;
+
+
+/// The name of the output which is drawn to the surface passed to `draw`
+pub const SCREEN_TARGET: &'static str = "screen";
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GraphError
+{
+    /// Two passes have the same name
+    DuplicatePass(String),
+    /// A texture is written by more than one pass or is written by a pass
+    /// while also being an external input
+    DuplicateOutput(String),
+    /// A pass reads a texture that is neither written by a pass nor an
+    /// external input
+    MissingInput{pass: String, texture: String},
+    /// A pass writes to `SCREEN_TARGET` and to textures, which can not be
+    /// bound at the same time
+    MixedScreenOutput(String),
+    /// A pass writes to nothing
+    NoOutputs(String),
+    /// The passes depend on each other in a cycle
+    Cycle(Vec<String>),
+    /// The fragment shader of a pass failed to compile or link
+    Shader{pass: String, message: String},
+}
+
+impl fmt::Display for GraphError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            GraphError::DuplicatePass(ref name) =>
+                write!(f, "More than one pass is named '{}'", name),
+            GraphError::DuplicateOutput(ref texture) =>
+                write!(f, "Texture '{}' is written more than once", texture),
+            GraphError::MissingInput{ref pass, ref texture} =>
+                write!(f, "Pass '{}' reads texture '{}' which nothing writes", pass, texture),
+            GraphError::MixedScreenOutput(ref pass) =>
+                write!(f, "Pass '{}' writes to the screen and to textures", pass),
+            GraphError::NoOutputs(ref pass) =>
+                write!(f, "Pass '{}' has no outputs", pass),
+            GraphError::Cycle(ref passes) =>
+                write!(f, "Passes {} depend on each other in a cycle", passes.join(", ")),
+            GraphError::Shader{ref pass, ref message} =>
+                write!(f, "The shader of pass '{}' is invalid: {}", pass, message),
+        }
+    }
+}
+
+impl Error for GraphError
+{
+    fn description(&self) -> &str
+    {
+        match *self
+        {
+            GraphError::DuplicatePass(_) => "duplicate pass",
+            GraphError::DuplicateOutput(_) => "duplicate output",
+            GraphError::MissingInput{..} => "missing input",
+            GraphError::MixedScreenOutput(_) => "pass writes to the screen and to textures",
+            GraphError::NoOutputs(_) => "pass without outputs",
+            GraphError::Cycle(_) => "cycle in render graph",
+            GraphError::Shader{..} => "invalid pass shader",
+        }
+    }
+}
+
+
+/**
+  The name of a pass and the textures it reads and writes
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct PassDescription
+{
+    pub name: String,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+impl PassDescription
+{
+    pub fn new(name: &str) -> PassDescription
+    {
+        PassDescription {
+            name: name.to_string(),
+            inputs: vec!(),
+            outputs: vec!(),
+        }
+    }
+
+    pub fn input(mut self, texture: &str) -> PassDescription
+    {
+        self.inputs.push(texture.to_string());
+        self
+    }
+
+    pub fn output(mut self, texture: &str) -> PassDescription
+    {
+        self.outputs.push(texture.to_string());
+        self
+    }
+}
+
+
+/**
+  Returns the indices of `passes` in the order they have to run in. Passes
+  that don't depend on each other keep the order they were declared in
+*/
+pub fn order_passes(passes: &[PassDescription], external_inputs: &[String])
+    -> Result<Vec<usize>, GraphError>
+{
+    let mut names = HashSet::new();
+    let mut writers = HashMap::new();
+    for (index, pass) in passes.iter().enumerate()
+    {
+        if !names.insert(&pass.name)
+        {
+            return Err(GraphError::DuplicatePass(pass.name.clone()));
+        }
+
+        if pass.outputs.is_empty()
+        {
+            return Err(GraphError::NoOutputs(pass.name.clone()));
+        }
+
+        if pass.outputs.len() > 1 && pass.outputs.iter().any(|output| output == SCREEN_TARGET)
+        {
+            return Err(GraphError::MixedScreenOutput(pass.name.clone()));
+        }
+
+        for output in &pass.outputs
+        {
+            if external_inputs.contains(output) || writers.insert(output, index).is_some()
+            {
+                return Err(GraphError::DuplicateOutput(output.clone()));
+            }
+        }
+    }
+
+    // The passes that each pass has to wait for
+    let mut dependencies = vec!();
+    for pass in passes
+    {
+        let mut waits_for = HashSet::new();
+        for input in &pass.inputs
+        {
+            if external_inputs.contains(input)
+            {
+                continue;
+            }
+
+            match writers.get(input)
+            {
+                Some(&writer) if input != SCREEN_TARGET => { waits_for.insert(writer); }
+                _ => return Err(GraphError::MissingInput {
+                    pass: pass.name.clone(),
+                    texture: input.clone()
+                })
+            }
+        }
+        dependencies.push(waits_for);
+    }
+
+    let mut order = vec!();
+    let mut done = vec!(false; passes.len());
+    while order.len() < passes.len()
+    {
+        let ready = (0..passes.len())
+            .filter(|&index| !done[index])
+            .filter(|&index| dependencies[index].iter().all(|&dependency| done[dependency]))
+            .next();
+
+        match ready
+        {
+            Some(index) => {
+                done[index] = true;
+                order.push(index);
+            }
+            None => {
+                let remaining = (0..passes.len())
+                    .filter(|&index| !done[index])
+                    .map(|index| passes[index].name.clone())
+                    .collect();
+                return Err(GraphError::Cycle(remaining));
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+
+struct Pass
+{
+    description: PassDescription,
+    shader: Program,
+}
+
+/**
+  The textures read by a pass followed by the user supplied parameters
+*/
+struct PassUniforms<'a>
+{
+    textures: Vec<(&'a str, &'a Texture2d)>,
+    parameters: &'a HashMap<String, f32>,
+}
+
+impl<'b> Uniforms for PassUniforms<'b>
+{
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut output: F)
+    {
+        let sampler = SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp
+            ),
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            .. Default::default()
+        };
+
+        for &(name, texture) in &self.textures
+        {
+            output(name, UniformValue::Texture2d(texture, Some(sampler)));
+        }
+        for (name, value) in self.parameters
+        {
+            output(name, UniformValue::Float(*value));
+        }
+    }
+}
+
+
+pub struct RenderGraphBuilder
+{
+    external_inputs: Vec<String>,
+    passes: Vec<(PassDescription, String)>,
+}
+
+impl RenderGraphBuilder
+{
+    pub fn new() -> RenderGraphBuilder
+    {
+        RenderGraphBuilder {
+            external_inputs: vec!(),
+            passes: vec!(),
+        }
+    }
+
+    /**
+      Declares a texture that is passed to `RenderGraph::draw` instead of
+      being written by a pass
+    */
+    pub fn external_input(mut self, name: &str) -> RenderGraphBuilder
+    {
+        self.external_inputs.push(name.to_string());
+        self
+    }
+
+    pub fn pass(mut self, description: PassDescription, fragment_source: &str) -> RenderGraphBuilder
+    {
+        self.passes.push((description, fragment_source.to_string()));
+        self
+    }
+
+    /**
+      Orders the passes, compiles their shaders and allocates the textures
+      they write to at `resolution`
+    */
+    pub fn build(self, facade: &Facade, resolution: (u32, u32)) -> Result<RenderGraph, GraphError>
+    {
+        let descriptions = self.passes.iter()
+            .map(|&(ref description, _)| description.clone())
+            .collect::<Vec<_>>();
+        let order = order_passes(&descriptions, &self.external_inputs)?;
+
+        let mut textures = HashMap::new();
+        for description in &descriptions
+        {
+            for output in description.outputs.iter().filter(|output| output.as_str() != SCREEN_TARGET)
+            {
+                let texture = Texture2d::empty_with_format(
+                        facade,
+                        UncompressedFloatFormat::F16F16F16F16,
+                        MipmapsOption::NoMipmap,
+                        resolution.0,
+                        resolution.1
+                    ).unwrap();
+                textures.insert(output.clone(), texture);
+            }
+        }
+
+        let mut passes = vec!();
+        for index in order
+        {
+            let (ref description, ref source) = self.passes[index];
+            let shader = Program::from_source(facade, rendering::VERTEX_SHADER, source, None)
+                .map_err(|e| GraphError::Shader {
+                    pass: description.name.clone(),
+                    message: e.to_string()
+                })?;
+
+            passes.push(Pass {
+                description: description.clone(),
+                shader: shader
+            });
+        }
+
+        Ok(RenderGraph {
+            context: facade.get_context().clone(),
+            external_inputs: self.external_inputs,
+            vertices: rendering::fullscreen_quad(facade),
+            passes: passes,
+            textures: textures,
+        })
+    }
+}
+
+
+pub struct RenderGraph
+{
+    context: Rc<Context>,
+    external_inputs: Vec<String>,
+    vertices: VertexBuffer<Vertex>,
+    /// Passes in the order they run in
+    passes: Vec<Pass>,
+    textures: HashMap<String, Texture2d>,
+}
+
+impl RenderGraph
+{
+    /**
+      Returns a texture written by one of the passes
+    */
+    pub fn texture(&self, name: &str) -> Option<&Texture2d>
+    {
+        self.textures.get(name)
+    }
+
+    /**
+      Runs all passes. `inputs` must contain every external input and
+      `parameters` are passed to every pass as float uniforms
+    */
+    pub fn draw<S: Surface>(
+                &self,
+                target: &mut S,
+                inputs: &HashMap<String, &Texture2d>,
+                parameters: &HashMap<String, f32>
+            ) -> Result<(), GraphError>
+    {
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+
+        for pass in &self.passes
+        {
+            let mut textures = vec!();
+            for input in &pass.description.inputs
+            {
+                let texture = if self.external_inputs.contains(input)
+                {
+                    inputs.get(input).map(|texture| *texture)
+                }
+                else
+                {
+                    self.textures.get(input)
+                };
+
+                match texture
+                {
+                    Some(texture) => textures.push((input.as_str(), texture)),
+                    None => return Err(GraphError::MissingInput {
+                        pass: pass.description.name.clone(),
+                        texture: input.clone()
+                    })
+                }
+            }
+
+            let uniforms = PassUniforms {
+                textures: textures,
+                parameters: parameters,
+            };
+
+            if pass.description.outputs.iter().any(|output| output == SCREEN_TARGET)
+            {
+                target.draw(&self.vertices, &indices, &pass.shader, &uniforms, &Default::default())
+                    .unwrap();
+            }
+            else
+            {
+                let outputs = pass.description.outputs.iter()
+                    .map(|output| (output.as_str(), &self.textures[output]))
+                    .collect::<Vec<_>>();
+
+                let mut framebuffer = MultiOutputFrameBuffer::new(&self.context, outputs).unwrap();
+                framebuffer.draw(&self.vertices, &indices, &pass.shader, &uniforms, &Default::default())
+                    .unwrap();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String>
+    {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn passes_run_after_the_passes_they_read_from()
+    {
+        let passes = [
+            PassDescription::new("combine").input("lit").input("bloom").output(SCREEN_TARGET),
+            PassDescription::new("blur").input("bright").output("bloom"),
+            PassDescription::new("light").input("diffuse").output("lit"),
+            PassDescription::new("threshold").input("emissive").output("bright"),
+        ];
+
+        let order = order_passes(&passes, &names(&["diffuse", "emissive"])).unwrap();
+
+        assert_eq!(order, vec!(2, 3, 1, 0));
+    }
+
+    #[test]
+    fn missing_inputs_are_reported()
+    {
+        let passes = [
+            PassDescription::new("light").input("diffuse").input("shadows").output("lit"),
+        ];
+
+        assert_eq!(
+            order_passes(&passes, &names(&["diffuse"])),
+            Err(GraphError::MissingInput{pass: "light".to_string(), texture: "shadows".to_string()})
+        );
+    }
+
+    #[test]
+    fn cycles_are_reported()
+    {
+        let passes = [
+            PassDescription::new("first").input("external").output("a"),
+            PassDescription::new("second").input("a").input("c").output("b"),
+            PassDescription::new("third").input("b").output("c"),
+        ];
+
+        assert_eq!(
+            order_passes(&passes, &names(&["external"])),
+            Err(GraphError::Cycle(names(&["second", "third"])))
+        );
+    }
+
+    #[test]
+    fn textures_can_only_be_written_once()
+    {
+        let passes = [
+            PassDescription::new("first").output("a"),
+            PassDescription::new("second").output("a"),
+        ];
+        assert_eq!(order_passes(&passes, &[]), Err(GraphError::DuplicateOutput("a".to_string())));
+
+        let passes = [PassDescription::new("first").output("diffuse")];
+        assert_eq!(
+            order_passes(&passes, &names(&["diffuse"])),
+            Err(GraphError::DuplicateOutput("diffuse".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_shaders_are_reported()
+    {
+        let renderer = ::headless::test_renderer(16, 16);
+
+        let result = RenderGraphBuilder::new()
+            .external_input("diffuse")
+            .pass(PassDescription::new("broken").input("diffuse").output(SCREEN_TARGET), "not glsl")
+            .build(&renderer, (16, 16));
+
+        match result
+        {
+            Err(GraphError::Shader{ref pass, ..}) => assert_eq!(pass, "broken"),
+            _ => panic!("Expected a shader error")
+        }
+    }
+
+    #[test]
+    fn passes_must_have_outputs()
+    {
+        let passes = [PassDescription::new("nothing").input("diffuse")];
+
+        assert_eq!(
+            order_passes(&passes, &names(&["diffuse"])),
+            Err(GraphError::NoOutputs("nothing".to_string()))
+        );
+    }
+
+    #[test]
+    fn passes_can_not_write_to_the_screen_and_textures()
+    {
+        let passes = [
+            PassDescription::new("combine").input("diffuse").output(SCREEN_TARGET).output("copy"),
+            PassDescription::new("blur").input("copy").output("blurred"),
+        ];
+
+        assert_eq!(
+            order_passes(&passes, &names(&["diffuse"])),
+            Err(GraphError::MixedScreenOutput("combine".to_string()))
+        );
+    }
+}
@@ -1,39 +1,82 @@
 use glium::texture::texture2d::Texture2d;
+use glium::texture::{MipmapsOption, UncompressedFloatFormat};
 use glium::backend::Facade;
 use glium::{Program, VertexBuffer};
 use glium::framebuffer::SimpleFrameBuffer;
 use glium;
 use glium::Surface;
 use glium::draw_parameters::DrawParameters;
+use glium::uniforms::MagnifySamplerFilter;
 
-use std::collections::{HashSet};
+use std::cell::Cell;
 
 use glium_types::Vertex;
 
-use rendering::RenderTargets;
+use bloom::{Bloom, BloomSettings};
+
+use rendering::{RenderStep, RenderTargets};
 
 
 pub const DEFAULT_FRAGMENT_SHADER: &'static str = include_str!("shaders/postprocess_frag.fs");
 
+/// The bloom is blurred at this fraction of the screen resolution
+const BLOOM_DOWNSAMPLE: u32 = 2;
+
 
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub enum RenderSteps
 {
     Diffuse,
     Emissive,
+    Light,
 }
 
-//TODO: Make a trait for this
-impl RenderSteps
+impl RenderStep for RenderSteps
 {
-    pub fn get_hash_set() -> HashSet<RenderSteps>
+    fn all() -> Vec<RenderSteps>
     {
-        let mut set = HashSet::new();
+        vec!(RenderSteps::Diffuse, RenderSteps::Emissive, RenderSteps::Light)
+    }
+
+    fn name(&self) -> &'static str
+    {
+        match *self
+        {
+            RenderSteps::Diffuse => "diffuse",
+            RenderSteps::Emissive => "emissive",
+            RenderSteps::Light => "light"
+        }
+    }
+}
 
-        set.insert(RenderSteps::Diffuse);
-        set.insert(RenderSteps::Emissive);
+/**
+  Parameters of the post processing pass which can be changed while the game
+  is running
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostProcessSettings
+{
+    /// Light that reaches everything, added to the light map. Dim by
+    /// default so that the shadows of lights are visible, scenes without
+    /// lights should raise it
+    pub ambient: (f32, f32, f32),
+    /// The glow around emissive sprites
+    pub bloom: BloomSettings,
+    /// The final colour is multiplied by this before gamma correction
+    pub exposure: f32,
+    pub gamma: f32,
+}
 
-        set
+impl Default for PostProcessSettings
+{
+    fn default() -> PostProcessSettings
+    {
+        PostProcessSettings {
+            ambient: (0.15, 0.15, 0.15),
+            bloom: BloomSettings::default(),
+            exposure: 1.,
+            gamma: 1.,
+        }
     }
 }
 
@@ -41,7 +84,11 @@ pub struct RenderParameters
 {
     diffuse_texture: Texture2d,
     emissive_texture: Texture2d,
-    ambient: f32,
+    light_texture: Texture2d,
+    bloom: Bloom,
+    // The render targets borrow the parameters for as long as they are used
+    // so the settings must be changeable through a shared reference
+    settings: Cell<PostProcessSettings>,
 }
 
 impl RenderParameters
@@ -53,9 +100,28 @@ impl RenderParameters
                 .unwrap(),
             emissive_texture: Texture2d::empty(facade, resolution.0, resolution.1)
                 .unwrap(),
-            ambient: 0.
+            // Lights add up to more than 1 so the light map needs a float format
+            light_texture: Texture2d::empty_with_format(
+                    facade,
+                    UncompressedFloatFormat::F16F16F16F16,
+                    MipmapsOption::NoMipmap,
+                    resolution.0,
+                    resolution.1
+                ).unwrap(),
+            bloom: Bloom::new(facade, resolution, BLOOM_DOWNSAMPLE),
+            settings: Cell::new(PostProcessSettings::default())
         }
     }
+
+    pub fn settings(&self) -> PostProcessSettings
+    {
+        self.settings.get()
+    }
+
+    pub fn set_settings(&self, settings: PostProcessSettings)
+    {
+        self.settings.set(settings);
+    }
 }
 
 impl RenderTargets<RenderSteps> for RenderParameters
@@ -65,14 +131,25 @@ impl RenderTargets<RenderSteps> for RenderParameters
         match *target
         {
             RenderSteps::Diffuse => self.diffuse_texture.as_surface(),
-            RenderSteps::Emissive => self.emissive_texture.as_surface()
+            RenderSteps::Emissive => self.emissive_texture.as_surface(),
+            RenderSteps::Light => self.light_texture.as_surface()
+        }
+    }
+
+    fn get_texture(&self, target: &RenderSteps) -> &Texture2d
+    {
+        match *target
+        {
+            RenderSteps::Diffuse => &self.diffuse_texture,
+            RenderSteps::Emissive => &self.emissive_texture,
+            RenderSteps::Light => &self.light_texture
         }
     }
 }
 
 
-pub fn default_render_function(
-            target: &mut glium::Frame,
+pub fn default_render_function<S: Surface>(
+            target: &mut S,
             uniforms: &RenderParameters,
             vertex_buffer: &VertexBuffer<Vertex>,
             shader: &Program
@@ -80,11 +157,19 @@ pub fn default_render_function(
 {
     let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
 
+    let settings = uniforms.settings();
+
+    uniforms.bloom.apply(&uniforms.emissive_texture, vertex_buffer, &settings.bloom);
+
     let uniform_object = uniform!{
         diffuse_texture: &uniforms.diffuse_texture,
-        emissive_texture: &uniforms.emissive_texture,
-        ambient: uniforms.ambient,
-        resolution: (target.get_dimensions().0 as f32, target.get_dimensions().1 as f32)
+        light_texture: &uniforms.light_texture,
+        bloom_texture: uniforms.bloom.result().sampled()
+            .magnify_filter(MagnifySamplerFilter::Linear),
+        ambient: settings.ambient,
+        bloom_strength: settings.bloom.strength,
+        exposure: settings.exposure,
+        gamma: settings.gamma
     };
 
     let draw_parameters = DrawParameters{
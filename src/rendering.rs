@@ -1,11 +1,18 @@
-use glium::{Program, VertexBuffer, Display};
+use glium::{Program, VertexBuffer, Surface};
+use glium::backend::Facade;
 use glium::framebuffer::SimpleFrameBuffer;
-use glium;
+use glium::texture::texture2d::Texture2d;
 
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
+use std::marker::PhantomData;
+
+use image::RgbaImage;
 
 use glium_types::Vertex;
+use drawing_util;
+use render_graph::{GraphError, RenderGraph, RenderGraphBuilder};
 
 
 pub const VERTEX_SHADER: &'static str = r#"
@@ -19,16 +26,62 @@ pub const VERTEX_SHADER: &'static str = r#"
         }
     "#;
 
+/**
+  An enum of render steps. Implementing `all` gives the set of steps that a
+  `RenderProcess` renders
+*/
+pub trait RenderStep: Clone + Eq + PartialEq + Hash + Sized
+{
+    fn all() -> Vec<Self>;
+
+    /// A short name of the step, used for naming captured images
+    fn name(&self) -> &'static str;
+
+    fn get_hash_set() -> HashSet<Self>
+    {
+        Self::all().into_iter().collect()
+    }
+}
+
+/**
+  Two triangles covering the whole target, for full screen passes
+*/
+pub fn fullscreen_quad(facade: &Facade) -> VertexBuffer<Vertex>
+{
+    let shape = vec!(
+            //First triangle
+            Vertex { position: (-1., -1.), tex_coords: (0., 0.) },
+            Vertex { position: (-1., 1.), tex_coords: (0., 1.) },
+            Vertex { position: (1., -1.), tex_coords: (1., 0.) },
+            //Second triangle
+            Vertex { position: (-1., 1.), tex_coords: (0., 1.) },
+            Vertex { position: (1., 1.), tex_coords: (1., 1.) },
+            Vertex { position: (1., -1.), tex_coords: (1., 0.) },
+        );
+
+    VertexBuffer::new(facade, &shape).unwrap()
+}
+
 pub trait RenderTargets<T>
     where T: Clone + Eq + PartialEq + Hash
 {
     fn get_render_target<'a>(&self, target: &T) -> SimpleFrameBuffer;
+
+    fn get_texture(&self, target: &T) -> &Texture2d;
 }
 
-pub struct RenderProcess<T, U, F>
-    where T: Eq + PartialEq + Hash + Clone,
+/**
+  Renders the targets of the steps `T` to a surface of type `S`, which is the
+  frame of the display when running the game and a texture in tests.
+
+  The targets are combined by `render_function` unless a render graph is
+  set, in which case the passes of the graph draw to the surface instead
+*/
+pub struct RenderProcess<T, U, S, F>
+    where T: RenderStep,
           U: RenderTargets<T>,
-          F: Fn(&mut glium::Frame, &U, &VertexBuffer<Vertex>, &Program)
+          S: Surface,
+          F: Fn(&mut S, &U, &VertexBuffer<Vertex>, &Program)
 {
     steps: HashSet<T>,
     uniforms: U,
@@ -36,35 +89,29 @@ pub struct RenderProcess<T, U, F>
     vertices: VertexBuffer<Vertex>,
     shader: Program,
 
-    render_function: F
+    render_function: F,
+    graph: Option<RenderGraph>,
+    /// Float uniforms passed to every pass of the graph
+    graph_parameters: RefCell<HashMap<String, f32>>,
+    surface: PhantomData<fn(&mut S)>
 }
 
-impl<T, U, F> RenderProcess<T, U, F>
-    where T: Eq + PartialEq + Hash + Clone,
+impl<T, U, S, F> RenderProcess<T, U, S, F>
+    where T: RenderStep,
           U: RenderTargets<T>,
-          F: Fn(&mut glium::Frame, &U, &VertexBuffer<Vertex>, &Program)
+          S: Surface,
+          F: Fn(&mut S, &U, &VertexBuffer<Vertex>, &Program)
 {
     pub fn new(
-                display: &Display,
+                display: &Facade,
                 steps: HashSet<T>,
                 uniforms: U,
                 fragment_source: &str,
                 render_function: F
             )
-            -> RenderProcess<T, U, F>
+            -> RenderProcess<T, U, S, F>
     {
-        let shape = vec!(
-                //First triangle
-                Vertex { position: (-1., -1.), tex_coords: (0., 0.) },
-                Vertex { position: (-1., 1.), tex_coords: (0., 1.) },
-                Vertex { position: (1., -1.), tex_coords: (1., 0.) },
-                //Second triangle                                
-                Vertex { position: (-1., 1.), tex_coords: (0., 1.) },
-                Vertex { position: (1., 1.), tex_coords: (1., 1.) },
-                Vertex { position: (1., -1.), tex_coords: (1., 0.) },
-            );
-
-        let vertices = VertexBuffer::new(display, &shape).unwrap();
+        let vertices = fullscreen_quad(display);
 
         let shader = Program::from_source(
                     display, 
@@ -79,8 +126,41 @@ impl<T, U, F> RenderProcess<T, U, F>
 
             vertices: vertices,
             shader: shader,
-            render_function: render_function
+            render_function: render_function,
+            graph: None,
+            graph_parameters: RefCell::new(HashMap::new()),
+            surface: PhantomData
+        }
+    }
+
+    /**
+      Replaces `render_function` with the passes of a render graph, which
+      are ordered by `order_passes`. The target of every step is an external
+      input of the graph named after the step, and the textures of the graph
+      are as large as the targets
+    */
+    pub fn set_graph(&mut self, facade: &Facade, builder: RenderGraphBuilder) -> Result<(), GraphError>
+    {
+        let mut builder = builder;
+        for step in &self.steps
+        {
+            builder = builder.external_input(step.name());
         }
+
+        let resolution = self.steps.iter().next()
+            .map(|step| self.uniforms.get_texture(step).dimensions())
+            .unwrap_or((1, 1));
+
+        self.graph = Some(builder.build(facade, resolution)?);
+        Ok(())
+    }
+
+    /**
+      Sets a float uniform of the passes of the render graph
+    */
+    pub fn set_graph_parameter(&self, name: &str, value: f32)
+    {
+        self.graph_parameters.borrow_mut().insert(name.to_string(), value);
     }
 
     pub fn get_targets(&self) -> HashMap<T, SimpleFrameBuffer>
@@ -93,9 +173,37 @@ impl<T, U, F> RenderProcess<T, U, F>
         map
     }
 
-    pub fn draw_to_display(&self, target: &mut glium::Frame)
+    /**
+      Reads back the targets of all steps, for example to save them next to
+      a screenshot of the final frame
+    */
+    pub fn capture_steps(&self) -> HashMap<T, RgbaImage>
     {
-        (self.render_function)(target, &self.uniforms, &self.vertices, &self.shader);
+        self.steps.iter()
+            .map(|step| (step.clone(), drawing_util::texture_to_image(self.uniforms.get_texture(step))))
+            .collect()
+    }
+
+    pub fn uniforms(&self) -> &U
+    {
+        &self.uniforms
+    }
+
+    pub fn draw_to_display(&self, target: &mut S)
+    {
+        match self.graph
+        {
+            Some(ref graph) => {
+                let inputs = self.steps.iter()
+                    .map(|step| (step.name().to_string(), self.uniforms.get_texture(step)))
+                    .collect();
+
+                // Every external input of the graph is a step so none can
+                // be missing
+                graph.draw(target, &inputs, &self.graph_parameters.borrow()).unwrap();
+            }
+            None => (self.render_function)(target, &self.uniforms, &self.vertices, &self.shader)
+        }
     }
 }
 
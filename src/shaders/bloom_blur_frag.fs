@@ -0,0 +1,22 @@
+#version 140
+
+in vec2 v_tex_coords;
+out vec4 color;
+uniform sampler2D source;
+// A step of one pixel along the axis that is blurred
+uniform vec2 direction;
+uniform float radius;
+uniform float sigma;
+void main() {
+    vec4 sum = vec4(0., 0., 0., 0.);
+    float total_weight = 0.;
+
+    for(float i = -radius; i <= radius; i++)
+    {
+        float weight = exp(-(i * i) / (2. * sigma * sigma));
+        sum += texture(source, v_tex_coords + direction * i) * weight;
+        total_weight += weight;
+    }
+
+    color = sum / total_weight;
+}
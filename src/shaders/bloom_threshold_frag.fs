@@ -0,0 +1,22 @@
+#version 140
+
+in vec2 v_tex_coords;
+out vec4 color;
+uniform sampler2D source;
+// Size of a pixel of the downsampled target in texture coordinates
+uniform vec2 texel_size;
+uniform float threshold;
+void main() {
+    // Four linearly filtered samples average the source pixels covered by
+    // this pixel of the smaller target
+    vec2 offset = texel_size * 0.25;
+    vec4 sum = texture(source, v_tex_coords + vec2(-offset.x, -offset.y))
+        + texture(source, v_tex_coords + vec2(offset.x, -offset.y))
+        + texture(source, v_tex_coords + vec2(-offset.x, offset.y))
+        + texture(source, v_tex_coords + vec2(offset.x, offset.y));
+    vec4 average = sum / 4.;
+
+    float brightness = max(average.r, max(average.g, average.b));
+    float factor = max(brightness - threshold, 0.) / max(brightness, 0.0001);
+    color = average * factor;
+}
@@ -3,27 +3,20 @@
 in vec2 v_tex_coords;
 out vec4 color;
 uniform sampler2D diffuse_texture;
-uniform sampler2D emissive_texture;
-uniform vec2 resolution;
+uniform sampler2D light_texture;
+// The emissive target blurred by the bloom passes
+uniform sampler2D bloom_texture;
+uniform vec3 ambient;
+uniform float bloom_strength;
+uniform float exposure;
+uniform float gamma;
 void main() {
-    vec4 emissive_color = vec4(0., 0., 0., 0.);
-
-    float blur_amount = 3;
-    for(float x = -blur_amount; x <= blur_amount; x++)
-    {
-        for(float y = -blur_amount; y <= blur_amount; y++)
-        {
-            vec2 coords = v_tex_coords + vec2(x / resolution.x, y / resolution.y);
-            emissive_color += texture(emissive_texture, coords);
-        }
-    }
-
-    emissive_color = emissive_color / (blur_amount * blur_amount * 2 * 2);
-
-    //emissive_color = texture(emissive_texture, v_tex_coords);
+    vec4 emissive_color = texture(bloom_texture, v_tex_coords) * bloom_strength;
 
     vec4 diffuse_color = texture(diffuse_texture, v_tex_coords);
-    //vec4 diffuse_color = vec4(0., 0., 0., 0.);
-    //emissive_color = vec4(0., 0., 0., 0.);
+    vec3 light = ambient + texture(light_texture, v_tex_coords).rgb;
+    diffuse_color.rgb *= light;
+
     color = diffuse_color + emissive_color;
+    color.rgb = pow(color.rgb * exposure, vec3(1. / gamma));
 }
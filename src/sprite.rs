@@ -4,6 +4,7 @@ extern crate nalgebra as na;
 use glium;
 use glium::texture::SrgbTexture2d;
 use glium::Surface;
+use glium::backend::Facade;
 use glium::framebuffer::SimpleFrameBuffer;
 use glium::draw_parameters::DrawParameters;
 
@@ -16,10 +17,35 @@ use camera_state::CameraState;
 use drawing_util;
 
 use render_steps::RenderSteps;
+use rendering::RenderStep;
+use atlas::{AtlasRegion, TextureAtlas};
+use sprite_batch::SpriteInstance;
+use layer::Layer;
+use collision::Aabb;
 
 use std::collections::HashMap;
 
 
+/**
+  A unit square with texture coordinates, which sprites scale to their size
+*/
+pub fn sprite_quad(display: &Facade) -> glium::VertexBuffer<Vertex>
+{
+    let shape = vec!(
+            //First triangle
+            Vertex { position: (0., 0.), tex_coords: (0., 0.) },
+            Vertex { position: (0., 1.), tex_coords: (0., 1.) },
+            Vertex { position: (1., 0.), tex_coords: (1., 0.) },
+            //Second triangle
+            Vertex { position: (0., 1.), tex_coords: (0., 1.) },
+            Vertex { position: (1., 1.), tex_coords: (1., 1.) },
+            Vertex { position: (1., 0.), tex_coords: (1., 0.) },
+        );
+
+    glium::VertexBuffer::new(display, &shape).unwrap()
+}
+
+
 pub struct SpriteFactory
 {
     vertex_buffer: Arc<glium::VertexBuffer<Vertex>>,
@@ -28,20 +54,9 @@ pub struct SpriteFactory
 
 impl SpriteFactory
 {
-    pub fn new(display: &glium::Display) -> SpriteFactory
+    pub fn new(display: &Facade) -> SpriteFactory
     {
-        let shape = vec!(
-                //First triangle
-                Vertex { position: (0., 0.), tex_coords: (0., 0.) },
-                Vertex { position: (0., 1.), tex_coords: (0., 1.) },
-                Vertex { position: (1., 0.), tex_coords: (1., 0.) },
-                //Second triangle                                
-                Vertex { position: (0., 1.), tex_coords: (0., 1.) },
-                Vertex { position: (1., 1.), tex_coords: (1., 1.) },
-                Vertex { position: (1., 0.), tex_coords: (1., 0.) },
-            );
-
-        let vertex_buffer = glium::VertexBuffer::new(display, &shape).unwrap();
+        let vertex_buffer = sprite_quad(display);
 
         let program = glium::Program::from_source(
                     display, 
@@ -58,7 +73,69 @@ impl SpriteFactory
 
     pub fn create_sprite(&self, texture: Arc<SrgbTexture2d>) -> Sprite
     {
-        Sprite::new(self.vertex_buffer.clone(), self.shader.clone(), texture)
+        Sprite::new(self.vertex_buffer.clone(), self.shader.clone(), SpriteTexture::whole(texture))
+    }
+
+    /**
+      Creates a sprite of the image `name` in `atlas`, including the
+      variants of the image for the other render steps. Returns None if the
+      atlas doesn't contain the image
+    */
+    pub fn create_atlas_sprite(&self, atlas: &TextureAtlas, name: &str) -> Option<Sprite>
+    {
+        atlas.get(name).map(|region| {
+            let mut sprite = Sprite::new(
+                    self.vertex_buffer.clone(),
+                    self.shader.clone(),
+                    SpriteTexture::from_region(region)
+                );
+
+            for step in RenderSteps::all().iter().filter(|step| **step != RenderSteps::Diffuse)
+            {
+                if let Some(region) = atlas.get_for_step(name, step)
+                {
+                    sprite.set_additional_region(step.clone(), region);
+                }
+            }
+
+            sprite
+        })
+    }
+}
+
+/**
+  A texture and the part of it that a sprite shows
+*/
+#[derive(Clone)]
+pub struct SpriteTexture
+{
+    pub texture: Arc<SrgbTexture2d>,
+    /// Position and size of the shown part in texture coordinates
+    pub uv_rect: (f32, f32, f32, f32),
+    /// Size of the shown part in pixels
+    pub size: (u32, u32),
+}
+
+impl SpriteTexture
+{
+    pub fn whole(texture: Arc<SrgbTexture2d>) -> SpriteTexture
+    {
+        let size = (texture.get_width(), texture.get_height().unwrap());
+
+        SpriteTexture {
+            texture: texture,
+            uv_rect: (0., 0., 1., 1.),
+            size: size
+        }
+    }
+
+    pub fn from_region(region: &AtlasRegion) -> SpriteTexture
+    {
+        SpriteTexture {
+            texture: region.page.clone(),
+            uv_rect: region.uv_rect,
+            size: region.size
+        }
     }
 }
 
@@ -67,9 +144,13 @@ pub struct Sprite
     position: na::Vector2<f32>,
     scale: na::Vector2<f32>,
     angle: f32,
-    textures: HashMap<RenderSteps, Option<Arc<SrgbTexture2d>>>,
+    textures: HashMap<RenderSteps, Option<SpriteTexture>>,
     aspect_ratio: f32,
+    layer: Layer,
+    /// Within a layer, sprites with a larger depth are drawn behind sprites
+    /// with a smaller depth
     depth: f32,
+    tint: (f32, f32, f32, f32),
 
     texture_size: (u32, u32),
 
@@ -84,11 +165,10 @@ impl Sprite
     fn new(
             vertex_buffer: Arc<glium::VertexBuffer<Vertex>>,
             shader: Arc<glium::Program>,
-            texture: Arc<SrgbTexture2d>
+            texture: SpriteTexture
         ) -> Sprite
     {
-        let texture_x = texture.get_width();
-        let texture_y = texture.get_height().unwrap();
+        let (texture_x, texture_y) = texture.size;
 
         let aspect_ratio = (texture_x as f32) 
                 / (texture_y as f32);
@@ -103,7 +183,9 @@ impl Sprite
             angle: 0.,
             textures: textures,
             aspect_ratio: aspect_ratio,
+            layer: Layer::Props,
             depth: 0.,
+            tint: (1., 1., 1., 1.),
 
             texture_size: (texture_x, texture_y),
 
@@ -143,9 +225,101 @@ impl Sprite
         return self.angle;
     }
 
+    pub fn set_layer(&mut self, layer: Layer)
+    {
+        self.layer = layer;
+    }
+
+    pub fn get_layer(&self) -> Layer
+    {
+        self.layer
+    }
+
+    /**
+      Sets the depth of the sprite within its layer. The depth must be
+      between -1 and 1
+    */
+    pub fn set_depth(&mut self, depth: f32)
+    {
+        self.depth = depth;
+    }
+
+    pub fn get_depth(&self) -> f32
+    {
+        self.depth
+    }
+
+    /**
+      Sets a colour that the texture is multiplied with
+    */
+    pub fn set_tint(&mut self, tint: (f32, f32, f32, f32))
+    {
+        self.tint = tint;
+    }
+
+    /**
+      Returns the texture and per instance data for drawing the sprite in
+      `step` as part of a `SpriteBatch`
+    */
+    pub fn instance(&self, step: &RenderSteps) -> Option<(Arc<SrgbTexture2d>, SpriteInstance)>
+    {
+        match self.textures.get(step)
+        {
+            Some(&Some(ref texture)) => Some((
+                texture.texture.clone(),
+                SpriteInstance {
+                    position: self.position,
+                    scale: self.scale,
+                    origin: self.origin,
+                    angle: self.angle,
+                    size: self.texture_size,
+                    uv_rect: texture.uv_rect,
+                    tint: self.tint,
+                    layer: self.layer,
+                    depth: self.depth
+                }
+            )),
+            _ => None
+        }
+    }
+
+    /**
+      The smallest box of world pixels containing the sprite, including its
+      rotation
+    */
+    pub fn world_bounds(&self) -> Aabb
+    {
+        world_bounds(self.scale, self.texture_size, self.position, self.origin, self.angle)
+    }
+
     pub fn set_additional_texture(&mut self, step: RenderSteps, texture: Arc<SrgbTexture2d>)
     {
-        self.textures.insert(step, Some(texture));
+        self.textures.insert(step, Some(SpriteTexture::whole(texture)));
+    }
+
+    pub fn set_additional_region(&mut self, step: RenderSteps, region: &AtlasRegion)
+    {
+        self.textures.insert(step, Some(SpriteTexture::from_region(region)));
+    }
+
+    /**
+      Replaces the textures of all steps, leaving the sprite without a
+      texture in the steps missing from `textures`. The size of the sprite
+      follows the diffuse texture
+    */
+    pub fn set_textures(&mut self, textures: &HashMap<RenderSteps, SpriteTexture>)
+    {
+        for step in RenderSteps::all()
+        {
+            let texture = textures.get(&step).cloned();
+            self.textures.insert(step, texture);
+        }
+
+        if let Some(texture) = textures.get(&RenderSteps::Diffuse)
+        {
+            self.texture_size = texture.size;
+            self.aspect_ratio = texture.size.0 as f32 / texture.size.1 as f32;
+        }
     }
 }
 
@@ -167,10 +341,13 @@ impl drawable::Drawable for Sprite
                     );
 
 
-                let texture = &**texture;
+                let uv_rect = texture.uv_rect;
+                let texture = &*texture.texture;
                 let matrix_data: [[f32;4]; 4]= *matrix.as_ref();
                 let uniforms = uniform! {
                     matrix: matrix_data,
+                    uv_rect: uv_rect,
+                    tint: self.tint,
                     tex: texture.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
                 };
 
@@ -248,7 +425,7 @@ pub fn generate_default_matrix(
             0., 0., 0., 1.
         );
 
-    let world_matrix = camera_state.get_matrix()
+    let world_matrix = camera_state.get_matrix(target_size)
         * drawing_util::get_window_scaling_matrix(
                     (target_size.0 as f32, target_size.1 as f32)
                 );
@@ -260,6 +437,34 @@ pub fn generate_default_matrix(
 }
 
 
+/**
+  The smallest box of world pixels containing a sprite drawn with the matrix
+  from `generate_default_matrix` with the same parameters
+*/
+pub fn world_bounds(
+            scale: na::Vector2<f32>,
+            texture_size: (u32, u32),
+            position: na::Vector2<f32>,
+            origin: na::Vector2<f32>,
+            angle: f32
+        ) -> Aabb
+{
+    let size = na::Vector2::new(scale.x * texture_size.0 as f32, scale.y * texture_size.1 as f32);
+    let (sin, cos) = (angle.sin(), angle.cos());
+
+    let corners = [(0., 0.), (1., 0.), (0., 1.), (1., 1.)].iter()
+        .map(|&(x, y)| {
+            let local = na::Vector2::new((x - origin.x) * size.x, (y - origin.y) * size.y);
+            position + na::Vector2::new(
+                local.x * cos - local.y * sin,
+                local.x * sin + local.y * cos
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Aabb::around_points(&corners)
+}
+
 
 
 #[cfg(test)]
@@ -364,4 +569,20 @@ mod tests
 
         assert_eq!(desired, result);
     }
+
+    #[test]
+    fn rotated_sprites_are_bounded_by_their_corners()
+    {
+        let bounds = world_bounds(
+                na::Vector2::new(1., 2.),
+                (10, 10),
+                na::Vector2::new(100., 100.),
+                na::Vector2::new(0.5, 0.5),
+                ::std::f32::consts::PI / 2.
+            );
+
+        let close = |a: na::Vector2<f32>, b: na::Vector2<f32>| (a.x - b.x).abs() < 0.001 && (a.y - b.y).abs() < 0.001;
+        assert!(close(bounds.min, na::Vector2::new(90., 95.)), "{:?}", bounds);
+        assert!(close(bounds.size, na::Vector2::new(20., 10.)), "{:?}", bounds);
+    }
 }
@@ -0,0 +1,337 @@
+/*!
+  Drawing many sprites with few draw calls.
+
+  Sprites are collected per render step and drawn back to front: by layer,
+  and within a layer from the largest depth to the smallest. Consecutive
+  sprites with the same texture are drawn with one instanced draw call where
+  the transform, texture rectangle, tint and depth of every sprite are per
+  instance attributes. Using an atlas puts most sprites on the same texture
+  so they can be drawn together.
+*/
+extern crate nalgebra as na;
+
+use glium;
+use glium::Surface;
+use glium::backend::{Context, Facade};
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::SrgbTexture2d;
+use glium::draw_parameters::DrawParameters;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use camera_state::CameraState;
+use constants::{INSTANCED_FRAGMENT_SHADER, INSTANCED_VERTEX_SHADER};
+use drawable::Drawable;
+use glium_types::Vertex;
+use layer::Layer;
+use render_steps::RenderSteps;
+use rendering::RenderStep;
+use sprite::{self, Sprite};
+
+use std::cmp::Ordering;
+
+
+/**
+  Everything needed to place one sprite. The fields have the same meaning as
+  the parameters of `sprite::generate_default_matrix`
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteInstance
+{
+    pub position: na::Vector2<f32>,
+    pub scale: na::Vector2<f32>,
+    pub origin: na::Vector2<f32>,
+    pub angle: f32,
+    /// Size of the sprite's image in pixels
+    pub size: (u32, u32),
+    pub uv_rect: (f32, f32, f32, f32),
+    pub tint: (f32, f32, f32, f32),
+    pub layer: Layer,
+    /// Sprites with a larger depth are drawn behind sprites with a smaller
+    /// depth on the same layer. This is also written as the depth of the
+    /// sprite, so it must be between -1 and 1
+    pub depth: f32,
+}
+
+
+/**
+  Returns the order to draw `instances` in so that translucent sprites blend
+  correctly. Sprites with the same layer and depth keep the order they were
+  added in
+*/
+pub fn draw_order(instances: &[SpriteInstance]) -> Vec<usize>
+{
+    let mut order = (0..instances.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&instances[a], &instances[b]);
+        a.layer.cmp(&b.layer)
+            .then(b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal))
+    });
+    order
+}
+
+#[derive(Copy, Clone)]
+struct InstanceAttributes
+{
+    matrix: [[f32; 4]; 4],
+    uv_rect: (f32, f32, f32, f32),
+    tint: (f32, f32, f32, f32),
+    depth: f32,
+}
+implement_vertex!(InstanceAttributes, matrix, uv_rect, tint, depth);
+
+
+/**
+  Consecutive sprites in the draw order which use the same texture
+*/
+struct TextureRun
+{
+    texture: Arc<SrgbTexture2d>,
+    instances: Vec<SpriteInstance>,
+}
+
+
+pub struct SpriteBatch
+{
+    context: Rc<Context>,
+    vertices: glium::VertexBuffer<Vertex>,
+    shader: glium::Program,
+
+    sprites: HashMap<RenderSteps, (Vec<Arc<SrgbTexture2d>>, Vec<SpriteInstance>)>,
+}
+
+impl SpriteBatch
+{
+    pub fn new(facade: &Facade) -> SpriteBatch
+    {
+        let program = glium::Program::from_source(
+                    facade,
+                    INSTANCED_VERTEX_SHADER,
+                    INSTANCED_FRAGMENT_SHADER,
+                    None
+                ).unwrap();
+
+        SpriteBatch {
+            context: facade.get_context().clone(),
+            vertices: sprite::sprite_quad(facade),
+            shader: program,
+
+            sprites: HashMap::new(),
+        }
+    }
+
+    /**
+      Adds `sprite` to every step that it has a texture for
+    */
+    pub fn add_sprite(&mut self, sprite: &Sprite)
+    {
+        for step in RenderSteps::all()
+        {
+            if let Some((texture, instance)) = sprite.instance(&step)
+            {
+                self.add_instance(step, texture, instance);
+            }
+        }
+    }
+
+    pub fn add_instance(&mut self, step: RenderSteps, texture: Arc<SrgbTexture2d>, instance: SpriteInstance)
+    {
+        let sprites = self.sprites.entry(step).or_insert_with(|| (vec!(), vec!()));
+        sprites.0.push(texture);
+        sprites.1.push(instance);
+    }
+
+    /**
+      Removes all sprites, to be called before adding the sprites of the
+      next frame
+    */
+    pub fn clear(&mut self)
+    {
+        self.sprites.clear();
+    }
+
+    /**
+      The number of draw calls `draw` makes for `step`
+    */
+    pub fn draw_call_count(&self, step: &RenderSteps) -> usize
+    {
+        self.texture_runs(step).len()
+    }
+
+    fn texture_runs(&self, step: &RenderSteps) -> Vec<TextureRun>
+    {
+        let mut runs: Vec<TextureRun> = vec!();
+
+        let &(ref textures, ref instances) = match self.sprites.get(step)
+        {
+            Some(sprites) => sprites,
+            None => return runs
+        };
+
+        for index in draw_order(instances)
+        {
+            let continues_run = runs.last()
+                .map(|run| Arc::ptr_eq(&run.texture, &textures[index]))
+                .unwrap_or(false);
+
+            if continues_run
+            {
+                runs.last_mut().unwrap().instances.push(instances[index]);
+            }
+            else
+            {
+                runs.push(TextureRun {
+                    texture: textures[index].clone(),
+                    instances: vec!(instances[index])
+                });
+            }
+        }
+
+        runs
+    }
+}
+
+impl Drawable for SpriteBatch
+{
+    fn draw(&self, target: &mut SimpleFrameBuffer, step: &RenderSteps, camera_state: &CameraState)
+    {
+        let runs = self.texture_runs(step);
+
+        let target_size = target.get_dimensions();
+        let draw_parameters = DrawParameters{
+            blend: glium::draw_parameters::Blend::alpha_blending(),
+            .. Default::default()
+        };
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+
+        for run in runs
+        {
+            let attributes = run.instances.iter()
+                .map(|instance| {
+                    let matrix = sprite::generate_default_matrix(
+                            instance.scale,
+                            instance.size,
+                            instance.position,
+                            instance.origin,
+                            instance.angle,
+                            target_size,
+                            camera_state
+                        );
+
+                    InstanceAttributes {
+                        matrix: *matrix.as_ref(),
+                        uv_rect: instance.uv_rect,
+                        tint: instance.tint,
+                        depth: instance.depth
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let instance_buffer = glium::VertexBuffer::dynamic(&self.context, &attributes).unwrap();
+
+            let uniforms = uniform! {
+                tex: run.texture.sampled()
+                    .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            };
+
+            target.draw(
+                    (&self.vertices, instance_buffer.per_instance().unwrap()),
+                    &indices,
+                    &self.shader,
+                    &uniforms,
+                    &draw_parameters
+                ).unwrap();
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+    use glium::texture::RawImage2d;
+    use headless;
+    use sprite::SpriteFactory;
+
+    fn instance(layer: Layer, depth: f32) -> SpriteInstance
+    {
+        SpriteInstance {
+            position: na::zero(),
+            scale: na::Vector2::new(1., 1.),
+            origin: na::zero(),
+            angle: 0.,
+            size: (1, 1),
+            uv_rect: (0., 0., 1., 1.),
+            tint: (1., 1., 1., 1.),
+            layer: layer,
+            depth: depth
+        }
+    }
+
+    #[test]
+    fn sprites_are_drawn_by_layer_then_back_to_front()
+    {
+        let instances = [
+            instance(Layer::Characters, 0.),
+            instance(Layer::Tiles, -0.5),
+            instance(Layer::Background, 0.),
+            instance(Layer::Tiles, 0.5),
+            instance(Layer::Tiles, -0.5),
+        ];
+
+        assert_eq!(draw_order(&instances), vec!(2, 3, 1, 4, 0));
+    }
+
+    #[test]
+    fn batches_draw_the_same_as_individual_sprites()
+    {
+        let renderer = headless::test_renderer(64, 64);
+
+        let pixels = (0..8 * 8)
+            .flat_map(|i| vec!((i * 4) as u8, 255 - (i * 4) as u8, 128, 255))
+            .collect::<Vec<_>>();
+        let texture = Arc::new(
+            SrgbTexture2d::new(&renderer, RawImage2d::from_raw_rgba(pixels, (8, 8))).unwrap()
+        );
+
+        let factory = SpriteFactory::new(&renderer);
+        let mut sprites = vec!();
+        for i in 0..4
+        {
+            let mut sprite = factory.create_sprite(texture.clone());
+            sprite.set_position(na::Vector2::new(10. * i as f32, 5. * i as f32));
+            sprite.set_scale(na::Vector2::new(1. + i as f32 * 0.5, 2.));
+            sprite.set_origin(na::Vector2::new(0.5, 0.5));
+            sprite.set_angle(i as f32 * 0.3);
+            sprites.push(sprite);
+        }
+
+        let mut camera_state = CameraState::new();
+        camera_state.set_position(na::Vector2::new(-0.2, 0.1));
+
+        let individual = headless::render_to_image(&renderer, (64, 64), |target| {
+            for sprite in &sprites
+            {
+                sprite.draw(target, &RenderSteps::Diffuse, &camera_state);
+            }
+        });
+
+        let mut batch = SpriteBatch::new(&renderer);
+        for sprite in &sprites
+        {
+            batch.add_sprite(sprite);
+        }
+        assert_eq!(batch.draw_call_count(&RenderSteps::Diffuse), 1);
+
+        let batched = headless::render_to_image(&renderer, (64, 64), |target| {
+            batch.draw(target, &RenderSteps::Diffuse, &camera_state);
+        });
+
+        assert_eq!(headless::compare_images(&individual, &batched, 1), Ok(()));
+    }
+}
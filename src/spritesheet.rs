@@ -0,0 +1,627 @@
+/*!
+  Loading animations exported as a sprite sheet with a json description, the
+  format written by Aseprite and by the sprite sheet exporters of Krita.
+
+  The json lists the rectangle and duration of every frame and, optionally,
+  named tags which each cover a range of frames. Every tag becomes an
+  `AnimationClip` and a sheet without tags becomes one clip named after the
+  json file. If there is an image next to the sheet image named
+  `<sheet>_emissive` it is used for the emissive step of the same frames.
+
+  Both the array and the hash layout of the frames are supported. The hash
+  layout is ordered by the number at the end of the frame name since the
+  order of the json object is not kept.
+*/
+use glium::backend::Facade;
+use glium::texture::SrgbTexture2d;
+
+use image;
+use serde_json::{self, Value};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use animation::{AnimationClip, AnimationFrame, LoopMode};
+use drawing_util;
+use render_steps::RenderSteps;
+use rendering::RenderStep;
+use sprite::SpriteTexture;
+
+
+#[derive(Debug)]
+pub enum SpriteSheetError
+{
+    Io(PathBuf, io::Error),
+    Json(serde_json::Error),
+    Image(PathBuf, image::ImageError),
+    /// `field` is missing from the part of the json described by `context`
+    MissingField{context: String, field: &'static str},
+    InvalidField{context: String, field: &'static str},
+    NoFrames,
+    FrameOutsideSheet{frame: String, rect: FrameRect, sheet_size: (u32, u32)},
+    EmptyFrame{frame: String},
+    TagOutOfRange{tag: String, from: usize, to: usize, frame_count: usize},
+    UnknownDirection{tag: String, direction: String},
+    /// The size of an image does not match the size of the sheet in the json
+    /// or the size of the diffuse sheet
+    SizeMismatch{path: PathBuf, expected: (u32, u32), actual: (u32, u32)},
+}
+
+impl fmt::Display for SpriteSheetError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            SpriteSheetError::Io(ref path, ref e) =>
+                write!(f, "Failed to read {}: {}", path.display(), e),
+            SpriteSheetError::Json(ref e) => write!(f, "Malformed sprite sheet json: {}", e),
+            SpriteSheetError::Image(ref path, ref e) =>
+                write!(f, "Failed to load image {}: {}", path.display(), e),
+            SpriteSheetError::MissingField{ref context, field} =>
+                write!(f, "Missing field '{}' in {}", field, context),
+            SpriteSheetError::InvalidField{ref context, field} =>
+                write!(f, "Invalid value for field '{}' in {}", field, context),
+            SpriteSheetError::NoFrames => write!(f, "The sprite sheet has no frames"),
+            SpriteSheetError::FrameOutsideSheet{ref frame, rect, sheet_size} =>
+                write!(
+                    f,
+                    "Frame '{}' at ({}, {}) of size {}x{} is outside the {}x{} sheet",
+                    frame, rect.x, rect.y, rect.w, rect.h, sheet_size.0, sheet_size.1
+                ),
+            SpriteSheetError::EmptyFrame{ref frame} => write!(f, "Frame '{}' has no pixels", frame),
+            SpriteSheetError::TagOutOfRange{ref tag, from, to, frame_count} =>
+                write!(
+                    f,
+                    "Tag '{}' covers frames {} to {} but the sheet has {} frames",
+                    tag, from, to, frame_count
+                ),
+            SpriteSheetError::UnknownDirection{ref tag, ref direction} =>
+                write!(f, "Tag '{}' has the unknown direction '{}'", tag, direction),
+            SpriteSheetError::SizeMismatch{ref path, expected, actual} =>
+                write!(
+                    f,
+                    "{} is {}x{} but should be {}x{}",
+                    path.display(), actual.0, actual.1, expected.0, expected.1
+                ),
+        }
+    }
+}
+
+impl Error for SpriteSheetError
+{
+    fn description(&self) -> &str
+    {
+        "failed to load sprite sheet"
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameRect
+{
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SheetFrame
+{
+    pub name: String,
+    pub rect: FrameRect,
+    /// How long the frame is shown in seconds
+    pub duration: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagDirection
+{
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameTag
+{
+    pub name: String,
+    /// The first and last frame of the tag, both included
+    pub from: usize,
+    pub to: usize,
+    pub direction: TagDirection,
+}
+
+impl FrameTag
+{
+    /**
+      The indices of the frames of the tag in the order they are played
+    */
+    pub fn frame_indices(&self) -> Vec<usize>
+    {
+        let mut indices = (self.from..self.to + 1).collect::<Vec<_>>();
+        match self.direction
+        {
+            TagDirection::Forward | TagDirection::PingPong => {}
+            TagDirection::Reverse | TagDirection::PingPongReverse => indices.reverse(),
+        }
+        indices
+    }
+
+    pub fn loop_mode(&self) -> LoopMode
+    {
+        match self.direction
+        {
+            TagDirection::Forward | TagDirection::Reverse => LoopMode::Loop,
+            TagDirection::PingPong | TagDirection::PingPongReverse => LoopMode::PingPong,
+        }
+    }
+}
+
+/**
+  The contents of a sprite sheet json file
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct SheetLayout
+{
+    /// The file name of the sheet image relative to the json file
+    pub image: Option<String>,
+    /// The size of the sheet image in pixels
+    pub size: Option<(u32, u32)>,
+    pub frames: Vec<SheetFrame>,
+    pub tags: Vec<FrameTag>,
+}
+
+impl SheetLayout
+{
+    /**
+      Checks that every frame is a non empty rectangle inside a sheet of
+      `sheet_size`
+    */
+    pub fn check_frames(&self, sheet_size: (u32, u32)) -> Result<(), SpriteSheetError>
+    {
+        for frame in &self.frames
+        {
+            let rect = frame.rect;
+            if rect.w == 0 || rect.h == 0
+            {
+                return Err(SpriteSheetError::EmptyFrame{frame: frame.name.clone()});
+            }
+            // Overflowing edges are outside of any sheet
+            let inside = |start: u32, length: u32, size: u32| {
+                start.checked_add(length).map(|end| end <= size).unwrap_or(false)
+            };
+            if !inside(rect.x, rect.w, sheet_size.0) || !inside(rect.y, rect.h, sheet_size.1)
+            {
+                return Err(SpriteSheetError::FrameOutsideSheet{
+                    frame: frame.name.clone(),
+                    rect: rect,
+                    sheet_size: sheet_size
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+
+fn field<'a>(value: &'a Value, context: &str, field: &'static str)
+    -> Result<&'a Value, SpriteSheetError>
+{
+    value.get(field).ok_or_else(|| SpriteSheetError::MissingField{
+        context: context.to_string(),
+        field: field
+    })
+}
+
+fn invalid(context: &str, field: &'static str) -> SpriteSheetError
+{
+    SpriteSheetError::InvalidField{context: context.to_string(), field: field}
+}
+
+fn u32_field(value: &Value, context: &str, name: &'static str) -> Result<u32, SpriteSheetError>
+{
+    field(value, context, name)?
+        .as_u64()
+        .and_then(|v| if v <= ::std::u32::MAX as u64 { Some(v as u32) } else { None })
+        .ok_or_else(|| invalid(context, name))
+}
+
+fn str_field<'a>(value: &'a Value, context: &str, name: &'static str)
+    -> Result<&'a str, SpriteSheetError>
+{
+    field(value, context, name)?
+        .as_str()
+        .ok_or_else(|| invalid(context, name))
+}
+
+/**
+  The number at the end of a frame name, ignoring the extension. Aseprite
+  names frames like `walk 12.aseprite`
+*/
+fn frame_number(name: &str) -> Option<u32>
+{
+    let stem = match name.rfind('.')
+    {
+        Some(index) => &name[..index],
+        None => name
+    };
+    let digits = stem.len() - stem.trim_right_matches(|c: char| c.is_digit(10)).len();
+    stem[stem.len() - digits..].parse().ok()
+}
+
+fn parse_frame(name: String, value: &Value, context: &str) -> Result<SheetFrame, SpriteSheetError>
+{
+    let rect = field(value, context, "frame")?;
+    let rect = FrameRect {
+        x: u32_field(rect, context, "x")?,
+        y: u32_field(rect, context, "y")?,
+        w: u32_field(rect, context, "w")?,
+        h: u32_field(rect, context, "h")?,
+    };
+
+    // Krita does not write durations, its frames are shown for 1/10 s
+    let duration = match value.get("duration")
+    {
+        Some(_) => u32_field(value, context, "duration")? as f32 / 1000.,
+        None => 0.1
+    };
+
+    Ok(SheetFrame { name, rect, duration })
+}
+
+fn parse_tag(value: &Value, index: usize, frame_count: usize) -> Result<FrameTag, SpriteSheetError>
+{
+    let context = format!("tag {}", index);
+    let name = str_field(value, &context, "name")?.to_string();
+    let context = format!("tag '{}'", name);
+    let from = u32_field(value, &context, "from")? as usize;
+    let to = u32_field(value, &context, "to")? as usize;
+
+    let direction = match value.get("direction").and_then(|v| v.as_str())
+    {
+        None | Some("forward") => TagDirection::Forward,
+        Some("reverse") => TagDirection::Reverse,
+        Some("pingpong") => TagDirection::PingPong,
+        Some("pingpong_reverse") => TagDirection::PingPongReverse,
+        Some(other) => return Err(SpriteSheetError::UnknownDirection{
+            tag: name,
+            direction: other.to_string()
+        })
+    };
+
+    if from > to || to >= frame_count
+    {
+        return Err(SpriteSheetError::TagOutOfRange{tag: name, from, to, frame_count});
+    }
+
+    Ok(FrameTag { name, from, to, direction })
+}
+
+/**
+  Reads the frames and tags of a sprite sheet json file
+*/
+pub fn parse_layout(json: &str) -> Result<SheetLayout, SpriteSheetError>
+{
+    let root: Value = serde_json::from_str(json).map_err(SpriteSheetError::Json)?;
+
+    let frames_value = field(&root, "the sheet", "frames")?;
+    let mut frames = vec!();
+    if let Some(array) = frames_value.as_array()
+    {
+        for (index, value) in array.iter().enumerate()
+        {
+            let name = value.get("filename")
+                .and_then(|name| name.as_str())
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| index.to_string());
+            let context = format!("frame {} ('{}')", index, name);
+            frames.push(parse_frame(name, value, &context)?);
+        }
+    }
+    else if let Some(object) = frames_value.as_object()
+    {
+        let mut names = object.keys().collect::<Vec<_>>();
+        names.sort_by_key(|name| (frame_number(name), name.to_string()));
+        for name in names
+        {
+            let context = format!("frame '{}'", name);
+            frames.push(parse_frame(name.clone(), &object[name], &context)?);
+        }
+    }
+    else
+    {
+        return Err(invalid("the sheet", "frames"));
+    }
+
+    if frames.is_empty()
+    {
+        return Err(SpriteSheetError::NoFrames);
+    }
+
+    let mut image = None;
+    let mut size = None;
+    let mut tags = vec!();
+    if let Some(meta) = root.get("meta")
+    {
+        image = meta.get("image").and_then(|image| image.as_str()).map(|image| image.to_string());
+
+        if let Some(size_value) = meta.get("size")
+        {
+            size = Some((u32_field(size_value, "meta.size", "w")?, u32_field(size_value, "meta.size", "h")?));
+        }
+
+        if let Some(tag_values) = meta.get("frameTags")
+        {
+            let tag_values = tag_values.as_array().ok_or_else(|| invalid("meta", "frameTags"))?;
+            for (index, value) in tag_values.iter().enumerate()
+            {
+                tags.push(parse_tag(value, index, frames.len())?);
+            }
+        }
+    }
+
+    Ok(SheetLayout { image, size, frames, tags })
+}
+
+
+/**
+  The path of the emissive variant of a sheet image, `sheet.png` has the
+  emissive variant `sheet_emissive.png`
+*/
+fn step_variant_path(path: &Path, step: &RenderSteps) -> Option<PathBuf>
+{
+    let stem = path.file_stem()?.to_str()?;
+    let name = match path.extension().and_then(|extension| extension.to_str())
+    {
+        Some(extension) => format!("{}_{}.{}", stem, step.name(), extension),
+        None => format!("{}_{}", stem, step.name())
+    };
+    Some(path.with_file_name(name))
+}
+
+fn load_sheet_texture(facade: &Facade, path: &Path, expected_size: Option<(u32, u32)>)
+    -> Result<Arc<SrgbTexture2d>, SpriteSheetError>
+{
+    let raw = drawing_util::try_load_texture(path)
+        .map_err(|e| SpriteSheetError::Image(path.to_path_buf(), e))?;
+
+    if let Some(expected) = expected_size
+    {
+        let actual = (raw.width, raw.height);
+        if actual != expected
+        {
+            return Err(SpriteSheetError::SizeMismatch{path: path.to_path_buf(), expected, actual});
+        }
+    }
+
+    Ok(Arc::new(SrgbTexture2d::new(facade, raw).unwrap()))
+}
+
+
+/**
+  The animation clips of a sprite sheet
+*/
+pub struct SpriteSheet
+{
+    clips: HashMap<String, Rc<AnimationClip>>,
+}
+
+impl SpriteSheet
+{
+    /**
+      Loads the sheet described by the json file at `path` along with its
+      emissive variant if there is one
+    */
+    pub fn load(facade: &Facade, path: &Path) -> Result<SpriteSheet, SpriteSheetError>
+    {
+        let mut json = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut json))
+            .map_err(|e| SpriteSheetError::Io(path.to_path_buf(), e))?;
+        let layout = parse_layout(&json)?;
+
+        let image_path = match layout.image
+        {
+            Some(ref image) => path.with_file_name(image),
+            None => path.with_extension("png")
+        };
+
+        let diffuse = load_sheet_texture(facade, &image_path, layout.size)?;
+        let sheet_size = (diffuse.get_width(), diffuse.get_height().unwrap());
+        layout.check_frames(sheet_size)?;
+
+        let mut textures = vec!((RenderSteps::Diffuse, diffuse));
+        for step in RenderSteps::all().into_iter().filter(|step| *step != RenderSteps::Diffuse)
+        {
+            if let Some(variant_path) = step_variant_path(&image_path, &step)
+            {
+                if variant_path.exists()
+                {
+                    let texture = load_sheet_texture(facade, &variant_path, Some(sheet_size))?;
+                    textures.push((step, texture));
+                }
+            }
+        }
+
+        let frames = layout.frames.iter()
+            .map(|frame| {
+                let rect = frame.rect;
+                let uv_rect = (
+                    rect.x as f32 / sheet_size.0 as f32,
+                    rect.y as f32 / sheet_size.1 as f32,
+                    rect.w as f32 / sheet_size.0 as f32,
+                    rect.h as f32 / sheet_size.1 as f32
+                );
+                let frame_textures = textures.iter()
+                    .map(|&(ref step, ref texture)| {
+                        (step.clone(), SpriteTexture {
+                            texture: texture.clone(),
+                            uv_rect: uv_rect,
+                            size: (rect.w, rect.h)
+                        })
+                    })
+                    .collect();
+                AnimationFrame::new(frame_textures, frame.duration)
+            })
+            .collect::<Vec<_>>();
+
+        let mut clips = HashMap::new();
+        if layout.tags.is_empty()
+        {
+            let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("default");
+            clips.insert(name.to_string(), Rc::new(AnimationClip::new(name, frames, LoopMode::Loop)));
+        }
+        for tag in &layout.tags
+        {
+            let tag_frames = tag.frame_indices()
+                .into_iter()
+                .map(|index| frames[index].clone())
+                .collect();
+            let clip = AnimationClip::new(&tag.name, tag_frames, tag.loop_mode());
+            clips.insert(tag.name.clone(), Rc::new(clip));
+        }
+
+        Ok(SpriteSheet { clips })
+    }
+
+    pub fn clip(&self, name: &str) -> Option<Rc<AnimationClip>>
+    {
+        self.clips.get(name).cloned()
+    }
+
+    /**
+      The names of all clips in alphabetical order
+    */
+    pub fn clip_names(&self) -> Vec<&str>
+    {
+        let mut names = self.clips.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const ARRAY_SHEET: &'static str = r#"{
+        "frames": [
+            {"filename": "walk 0.aseprite", "frame": {"x": 0, "y": 0, "w": 16, "h": 32}, "duration": 100},
+            {"filename": "walk 1.aseprite", "frame": {"x": 16, "y": 0, "w": 16, "h": 32}, "duration": 150},
+            {"filename": "walk 2.aseprite", "frame": {"x": 32, "y": 0, "w": 16, "h": 32}, "duration": 100}
+        ],
+        "meta": {
+            "image": "walk.png",
+            "size": {"w": 48, "h": 32},
+            "frameTags": [
+                {"name": "walk", "from": 0, "to": 2, "direction": "pingpong"},
+                {"name": "back", "from": 1, "to": 2, "direction": "reverse"}
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn array_sheets_are_parsed()
+    {
+        let layout = parse_layout(ARRAY_SHEET).unwrap();
+
+        assert_eq!(layout.image, Some("walk.png".to_string()));
+        assert_eq!(layout.size, Some((48, 32)));
+        assert_eq!(layout.frames.len(), 3);
+        assert_eq!(layout.frames[1].rect, FrameRect{x: 16, y: 0, w: 16, h: 32});
+        assert_eq!(layout.frames[1].duration, 0.15);
+        assert_eq!(layout.tags[0].loop_mode(), LoopMode::PingPong);
+        assert_eq!(layout.tags[0].frame_indices(), vec!(0, 1, 2));
+        assert_eq!(layout.tags[1].frame_indices(), vec!(2, 1));
+        assert!(layout.check_frames((48, 32)).is_ok());
+    }
+
+    #[test]
+    fn hash_sheets_are_ordered_by_frame_number()
+    {
+        let frames = (0..12)
+            .map(|i| format!(r#""run {}.aseprite": {{"frame": {{"x": {}, "y": 0, "w": 8, "h": 8}}, "duration": 50}}"#, i, i * 8))
+            .collect::<Vec<_>>()
+            .join(",");
+        let json = format!(r#"{{"frames": {{{}}}}}"#, frames);
+
+        let layout = parse_layout(&json).unwrap();
+
+        let xs = layout.frames.iter().map(|frame| frame.rect.x).collect::<Vec<_>>();
+        assert_eq!(xs, (0..12).map(|i| i * 8).collect::<Vec<_>>());
+        assert!(layout.tags.is_empty());
+    }
+
+    #[test]
+    fn malformed_frames_are_described()
+    {
+        let missing = r#"{"frames": [{"frame": {"x": 0, "y": 0, "w": 8}}]}"#;
+        match parse_layout(missing)
+        {
+            Err(SpriteSheetError::MissingField{context, field}) => {
+                assert_eq!(context, "frame 0 ('0')");
+                assert_eq!(field, "h");
+            }
+            other => panic!("Unexpected result {:?}", other.map(|_| ()))
+        }
+
+        let outside = parse_layout(r#"{"frames": [{"frame": {"x": 40, "y": 0, "w": 16, "h": 16}}]}"#)
+            .unwrap();
+        match outside.check_frames((48, 16))
+        {
+            Err(SpriteSheetError::FrameOutsideSheet{frame, ..}) => assert_eq!(frame, "0"),
+            other => panic!("Unexpected result {:?}", other)
+        }
+
+        match parse_layout(r#"{"frames": []}"#)
+        {
+            Err(SpriteSheetError::NoFrames) => {}
+            other => panic!("Unexpected result {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn out_of_range_numbers_are_errors()
+    {
+        let too_large = r#"{"frames": [{"frame": {"x": 4294967297, "y": 0, "w": 8, "h": 8}}]}"#;
+        match parse_layout(too_large)
+        {
+            Err(SpriteSheetError::InvalidField{field, ..}) => assert_eq!(field, "x"),
+            other => panic!("Unexpected result {:?}", other.map(|_| ()))
+        }
+
+        let overflowing = parse_layout(r#"{"frames": [{"frame": {"x": 4294967295, "y": 0, "w": 8, "h": 8}}]}"#)
+            .unwrap();
+        match overflowing.check_frames((48, 16))
+        {
+            Err(SpriteSheetError::FrameOutsideSheet{frame, ..}) => assert_eq!(frame, "0"),
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn tags_outside_the_frames_are_errors()
+    {
+        let json = ARRAY_SHEET.replace(r#""from": 1, "to": 2"#, r#""from": 1, "to": 5"#);
+
+        match parse_layout(&json)
+        {
+            Err(SpriteSheetError::TagOutOfRange{tag, from, to, frame_count}) => {
+                assert_eq!((tag.as_str(), from, to, frame_count), ("back", 1, 5, 3));
+            }
+            other => panic!("Unexpected result {:?}", other.map(|_| ()))
+        }
+    }
+}
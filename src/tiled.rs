@@ -0,0 +1,855 @@
+/*!
+  Importer for maps made in the Tiled editor, both in the xml (.tmx) and the
+  json (.tmj) format.
+
+  Tile layers are turned into blocks using a `TilesetTable` and object layers
+  are turned into a list of `PlacedObject`s. Only orthogonal, finite maps are
+  supported and tile layers in the xml format must use the csv or xml
+  encoding.
+*/
+extern crate nalgebra as na;
+
+use serde_json::{self, Value};
+use xml::reader::{EventReader, XmlEvent};
+use xml::attribute::OwnedAttribute;
+use xml;
+
+use grid::{Grid, BlockType, BLOCK_SIZE};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+
+/// The upper bits of a tile id store flipping and rotation flags
+const TILE_FLAG_MASK: u32 = 0xF0000000;
+
+
+#[derive(Debug, PartialEq)]
+pub enum UnsupportedFeature {
+    InfiniteMap,
+    Orientation(String),
+    Encoding(String),
+    Compression(String),
+    FileFormat(String),
+}
+
+#[derive(Debug)]
+pub enum TiledError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Xml(xml::reader::Error),
+    Unsupported(UnsupportedFeature),
+    MissingField(&'static str),
+    InvalidField(&'static str),
+    /// A tile id which is not part of any tileset in the map
+    UnknownTile(u32),
+    /// A tile which does not have a block type in the `TilesetTable`
+    UnmappedTile {
+        tileset: String,
+        local_id: u32,
+        layer: String,
+        position: na::Vector2<i32>
+    },
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TiledError::Io(ref e) => write!(f, "failed to read map: {}", e),
+            TiledError::Json(ref e) => write!(f, "malformed json map: {}", e),
+            TiledError::Xml(ref e) => write!(f, "malformed xml map: {}", e),
+            TiledError::Unsupported(ref feature) => write!(f, "unsupported map feature: {:?}", feature),
+            TiledError::MissingField(field) => write!(f, "missing field '{}'", field),
+            TiledError::InvalidField(field) => write!(f, "invalid value for field '{}'", field),
+            TiledError::UnknownTile(gid) => write!(f, "tile id {} is not part of any tileset", gid),
+            TiledError::UnmappedTile{ref tileset, local_id, ref layer, position} =>
+                write!(
+                    f,
+                    "tile {} of tileset '{}' at ({}, {}) in layer '{}' has no block type",
+                    local_id, tileset, position.x, position.y, layer
+                ),
+        }
+    }
+}
+
+impl Error for TiledError {
+    fn description(&self) -> &str {
+        "failed to import tiled map"
+    }
+}
+
+impl From<io::Error> for TiledError {
+    fn from(e: io::Error) -> Self {
+        TiledError::Io(e)
+    }
+}
+
+
+/**
+  Maps tiles in named tilesets to block types. Embedded tilesets are
+  identified by their name and external ones by the file name of their
+  source without the extension
+*/
+pub struct TilesetTable {
+    tiles: HashMap<(String, u32), BlockType>
+}
+
+impl TilesetTable {
+    pub fn new() -> Self {
+        Self {
+            tiles: HashMap::new()
+        }
+    }
+
+    pub fn with_tile(mut self, tileset: &str, local_id: u32, block_type: BlockType) -> Self {
+        self.insert(tileset, local_id, block_type);
+        self
+    }
+
+    pub fn insert(&mut self, tileset: &str, local_id: u32, block_type: BlockType) {
+        self.tiles.insert((tileset.to_string(), local_id), block_type);
+    }
+
+    pub fn get(&self, tileset: &str, local_id: u32) -> Option<BlockType> {
+        self.tiles.get(&(tileset.to_string(), local_id)).cloned()
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ObjectKind {
+    Lamp,
+    Tree,
+    SpawnPoint,
+    Other(String)
+}
+
+impl ObjectKind {
+    fn from_type_name(name: &str) -> ObjectKind {
+        match &*name.to_lowercase() {
+            "lamp" => ObjectKind::Lamp,
+            "tree" => ObjectKind::Tree,
+            "spawn" | "spawnpoint" | "spawn_point" => ObjectKind::SpawnPoint,
+            _ => ObjectKind::Other(name.to_string())
+        }
+    }
+}
+
+/**
+  An object from an object layer. Position and size are in world pixels
+  with the position being the top left corner of the object
+*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlacedObject {
+    pub kind: ObjectKind,
+    pub name: String,
+    pub position: na::Vector2<f32>,
+    pub size: na::Vector2<f32>
+}
+
+pub struct TiledMap {
+    pub blocks: Vec<(BlockType, na::Vector2<i32>)>,
+    pub objects: Vec<PlacedObject>
+}
+
+impl TiledMap {
+    pub fn build_grid(&self) -> Grid {
+        let mut grid = Grid::new();
+        grid.add_prefab(self.blocks.clone(), na::zero());
+        grid
+    }
+}
+
+
+/*
+  Format independent representation of the parts of a map that we use
+*/
+struct RawTileset {
+    first_gid: u32,
+    key: String
+}
+
+struct RawObject {
+    name: String,
+    type_name: String,
+    position: na::Vector2<f32>,
+    size: na::Vector2<f32>,
+    gid: Option<u32>
+}
+
+enum RawLayer {
+    Tiles {
+        name: String,
+        width: u32,
+        data: Vec<u32>
+    },
+    Objects(Vec<RawObject>)
+}
+
+struct RawMap {
+    orientation: String,
+    infinite: bool,
+    tile_size: na::Vector2<f32>,
+    tilesets: Vec<RawTileset>,
+    layers: Vec<RawLayer>
+}
+
+fn tileset_key(name: Option<&str>, source: Option<&str>) -> Result<String, TiledError> {
+    match (name, source) {
+        (Some(name), _) => Ok(name.to_string()),
+        (None, Some(source)) => Path::new(source)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(|stem| stem.to_string())
+            .ok_or(TiledError::InvalidField("source")),
+        (None, None) => Err(TiledError::MissingField("name"))
+    }
+}
+
+fn check_supported(orientation: &str, infinite: bool) -> Result<(), TiledError> {
+    if infinite {
+        return Err(TiledError::Unsupported(UnsupportedFeature::InfiniteMap));
+    }
+    if orientation != "orthogonal" {
+        return Err(TiledError::Unsupported(UnsupportedFeature::Orientation(orientation.to_string())));
+    }
+    Ok(())
+}
+
+fn build_map(raw: RawMap, table: &TilesetTable) -> Result<TiledMap, TiledError> {
+    check_supported(&raw.orientation, raw.infinite)?;
+    if raw.tile_size.x <= 0. || raw.tile_size.y <= 0. {
+        return Err(TiledError::InvalidField("tilewidth"));
+    }
+
+    let mut tilesets = raw.tilesets;
+    tilesets.sort_by_key(|tileset| tileset.first_gid);
+
+    let mut blocks = HashMap::new();
+    let mut objects = vec!();
+
+    let object_scale = na::Vector2::new(
+            BLOCK_SIZE / raw.tile_size.x,
+            BLOCK_SIZE / raw.tile_size.y
+        );
+
+    for layer in raw.layers {
+        match layer {
+            RawLayer::Tiles{name, width, data} => {
+                if width == 0 {
+                    return Err(TiledError::InvalidField("width"));
+                }
+
+                for (index, gid) in data.into_iter().enumerate() {
+                    let gid = gid & !TILE_FLAG_MASK;
+                    if gid == 0 {
+                        continue;
+                    }
+
+                    let position = na::Vector2::new(
+                            (index as u32 % width) as i32,
+                            (index as u32 / width) as i32
+                        );
+
+                    let tileset = tilesets.iter()
+                        .rev()
+                        .find(|tileset| tileset.first_gid <= gid)
+                        .ok_or(TiledError::UnknownTile(gid))?;
+
+                    let local_id = gid - tileset.first_gid;
+                    let block_type = table.get(&tileset.key, local_id)
+                        .ok_or_else(|| TiledError::UnmappedTile {
+                            tileset: tileset.key.clone(),
+                            local_id,
+                            layer: name.clone(),
+                            position
+                        })?;
+
+                    // Later layers are drawn on top in Tiled so they replace
+                    // earlier blocks
+                    blocks.insert(position, block_type);
+                }
+            }
+            RawLayer::Objects(layer_objects) => {
+                for object in layer_objects {
+                    let mut position = object.position;
+                    // Tile objects are anchored at their bottom left corner
+                    if object.gid.is_some() {
+                        position.y -= object.size.y;
+                    }
+
+                    objects.push(PlacedObject {
+                        kind: ObjectKind::from_type_name(&object.type_name),
+                        name: object.name,
+                        position: na::Vector2::new(
+                                position.x * object_scale.x,
+                                position.y * object_scale.y
+                            ),
+                        size: na::Vector2::new(
+                                object.size.x * object_scale.x,
+                                object.size.y * object_scale.y
+                            )
+                    });
+                }
+            }
+        }
+    }
+
+    let mut blocks = blocks.into_iter()
+        .map(|(position, block_type)| (block_type, position))
+        .collect::<Vec<_>>();
+    blocks.sort_by_key(|&(_, pos)| (pos.y, pos.x));
+
+    Ok(TiledMap { blocks, objects })
+}
+
+
+
+/*
+  Json (.tmj) parsing
+*/
+fn json_field<'a>(value: &'a Value, field: &'static str) -> Result<&'a Value, TiledError> {
+    value.get(field).ok_or(TiledError::MissingField(field))
+}
+
+fn json_u32(value: &Value, field: &'static str) -> Result<u32, TiledError> {
+    json_field(value, field)?
+        .as_u64()
+        .map(|v| v as u32)
+        .ok_or(TiledError::InvalidField(field))
+}
+
+fn json_f32(value: &Value, field: &'static str) -> Result<f32, TiledError> {
+    json_field(value, field)?
+        .as_f64()
+        .map(|v| v as f32)
+        .ok_or(TiledError::InvalidField(field))
+}
+
+fn json_optional_f32(value: &Value, field: &'static str) -> Result<f32, TiledError> {
+    match value.get(field) {
+        Some(_) => json_f32(value, field),
+        None => Ok(0.)
+    }
+}
+
+fn json_optional_str<'a>(value: &'a Value, field: &'static str) -> Option<&'a str> {
+    value.get(field).and_then(|v| v.as_str())
+}
+
+fn json_array<'a>(value: &'a Value, field: &'static str) -> Result<&'a Vec<Value>, TiledError> {
+    json_field(value, field)?
+        .as_array()
+        .ok_or(TiledError::InvalidField(field))
+}
+
+fn parse_json_layers(layers: &[Value], result: &mut Vec<RawLayer>) -> Result<(), TiledError> {
+    for layer in layers {
+        match json_optional_str(layer, "type") {
+            Some("tilelayer") => {
+                if let Some(encoding) = json_optional_str(layer, "encoding") {
+                    if encoding != "csv" {
+                        return Err(TiledError::Unsupported(
+                                UnsupportedFeature::Encoding(encoding.to_string())
+                            ));
+                    }
+                }
+
+                let data = json_array(layer, "data")?
+                    .iter()
+                    .map(|gid| gid.as_u64().map(|gid| gid as u32).ok_or(TiledError::InvalidField("data")))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                result.push(RawLayer::Tiles {
+                    name: json_optional_str(layer, "name").unwrap_or("").to_string(),
+                    width: json_u32(layer, "width")?,
+                    data
+                });
+            }
+            Some("objectgroup") => {
+                let mut objects = vec!();
+                for object in json_array(layer, "objects")? {
+                    // Newer versions of Tiled call the type `class`
+                    let type_name = json_optional_str(object, "type")
+                        .or(json_optional_str(object, "class"))
+                        .unwrap_or("");
+
+                    objects.push(RawObject {
+                        name: json_optional_str(object, "name").unwrap_or("").to_string(),
+                        type_name: type_name.to_string(),
+                        position: na::Vector2::new(json_f32(object, "x")?, json_f32(object, "y")?),
+                        size: na::Vector2::new(
+                                json_optional_f32(object, "width")?,
+                                json_optional_f32(object, "height")?
+                            ),
+                        gid: match object.get("gid") {
+                            Some(_) => Some(json_u32(object, "gid")?),
+                            None => None
+                        }
+                    });
+                }
+                result.push(RawLayer::Objects(objects));
+            }
+            Some("group") => {
+                parse_json_layers(json_array(layer, "layers")?, result)?;
+            }
+            // Image layers have nothing to import
+            Some(_) => {}
+            None => return Err(TiledError::MissingField("type"))
+        }
+    }
+    Ok(())
+}
+
+fn parse_json_map(source: &str) -> Result<RawMap, TiledError> {
+    let map: Value = serde_json::from_str(source).map_err(TiledError::Json)?;
+
+    // Layers of unsupported maps are stored differently, infinite maps have
+    // chunks instead of data, so they are rejected before parsing layers
+    let orientation = json_field(&map, "orientation")?
+        .as_str()
+        .ok_or(TiledError::InvalidField("orientation"))?
+        .to_string();
+    let infinite = map.get("infinite").and_then(|v| v.as_bool()).unwrap_or(false);
+    check_supported(&orientation, infinite)?;
+
+    let mut tilesets = vec!();
+    for tileset in json_array(&map, "tilesets")? {
+        tilesets.push(RawTileset {
+            first_gid: json_u32(tileset, "firstgid")?,
+            key: tileset_key(
+                    json_optional_str(tileset, "name"),
+                    json_optional_str(tileset, "source")
+                )?
+        });
+    }
+
+    let mut layers = vec!();
+    parse_json_layers(json_array(&map, "layers")?, &mut layers)?;
+
+    Ok(RawMap {
+        orientation,
+        infinite,
+        tile_size: na::Vector2::new(
+                json_f32(&map, "tilewidth")?,
+                json_f32(&map, "tileheight")?
+            ),
+        tilesets,
+        layers
+    })
+}
+
+
+
+/*
+  Xml (.tmx) parsing
+*/
+fn xml_attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter()
+        .find(|attribute| attribute.name.local_name == name)
+        .map(|attribute| &*attribute.value)
+}
+
+fn xml_parsed_attribute<T: ::std::str::FromStr>(
+            attributes: &[OwnedAttribute],
+            name: &'static str
+        ) -> Result<T, TiledError>
+{
+    xml_attribute(attributes, name)
+        .ok_or(TiledError::MissingField(name))?
+        .trim()
+        .parse()
+        .map_err(|_| TiledError::InvalidField(name))
+}
+
+fn xml_optional_attribute<T: ::std::str::FromStr>(
+            attributes: &[OwnedAttribute],
+            name: &'static str,
+            default: T
+        ) -> Result<T, TiledError>
+{
+    match xml_attribute(attributes, name) {
+        Some(_) => xml_parsed_attribute(attributes, name),
+        None => Ok(default)
+    }
+}
+
+struct XmlTileLayer {
+    name: String,
+    width: u32,
+    csv: Option<String>,
+    tiles: Vec<u32>
+}
+
+fn parse_xml_map(source: &str) -> Result<RawMap, TiledError> {
+    let mut map = None;
+    let mut tilesets = vec!();
+    let mut layers = vec!();
+
+    let mut tile_layer: Option<XmlTileLayer> = None;
+    let mut in_data = false;
+    // Tiles in a tileset can have object groups with their collision shapes
+    // which are not objects in the level
+    let mut in_tileset = false;
+    let mut objects: Option<Vec<RawObject>> = None;
+
+    for event in EventReader::from_str(source) {
+        match event.map_err(TiledError::Xml)? {
+            XmlEvent::StartElement{name, attributes, ..} => {
+                match &*name.local_name {
+                    "map" => {
+                        map = Some(RawMap {
+                            orientation: xml_attribute(&attributes, "orientation")
+                                .ok_or(TiledError::MissingField("orientation"))?
+                                .to_string(),
+                            infinite: xml_optional_attribute::<u32>(&attributes, "infinite", 0)? != 0,
+                            tile_size: na::Vector2::new(
+                                    xml_parsed_attribute(&attributes, "tilewidth")?,
+                                    xml_parsed_attribute(&attributes, "tileheight")?
+                                ),
+                            tilesets: vec!(),
+                            layers: vec!()
+                        });
+                    }
+                    "tileset" => {
+                        in_tileset = true;
+                        tilesets.push(RawTileset {
+                            first_gid: xml_parsed_attribute(&attributes, "firstgid")?,
+                            key: tileset_key(
+                                    xml_attribute(&attributes, "name"),
+                                    xml_attribute(&attributes, "source")
+                                )?
+                        });
+                    }
+                    "layer" => {
+                        tile_layer = Some(XmlTileLayer {
+                            name: xml_attribute(&attributes, "name").unwrap_or("").to_string(),
+                            width: xml_parsed_attribute(&attributes, "width")?,
+                            csv: None,
+                            tiles: vec!()
+                        });
+                    }
+                    "data" => {
+                        if let Some(compression) = xml_attribute(&attributes, "compression") {
+                            return Err(TiledError::Unsupported(
+                                    UnsupportedFeature::Compression(compression.to_string())
+                                ));
+                        }
+                        match xml_attribute(&attributes, "encoding") {
+                            Some("csv") => {
+                                if let Some(ref mut layer) = tile_layer {
+                                    layer.csv = Some(String::new());
+                                }
+                            }
+                            Some(encoding) => {
+                                return Err(TiledError::Unsupported(
+                                        UnsupportedFeature::Encoding(encoding.to_string())
+                                    ));
+                            }
+                            None => {}
+                        }
+                        in_data = true;
+                    }
+                    "chunk" => {
+                        return Err(TiledError::Unsupported(UnsupportedFeature::InfiniteMap));
+                    }
+                    "tile" if in_data => {
+                        let gid = xml_optional_attribute(&attributes, "gid", 0)?;
+                        if let Some(ref mut layer) = tile_layer {
+                            layer.tiles.push(gid);
+                        }
+                    }
+                    "objectgroup" if !in_tileset => {
+                        objects = Some(vec!());
+                    }
+                    "object" if !in_tileset => {
+                        let type_name = xml_attribute(&attributes, "type")
+                            .or(xml_attribute(&attributes, "class"))
+                            .unwrap_or("");
+
+                        let object = RawObject {
+                            name: xml_attribute(&attributes, "name").unwrap_or("").to_string(),
+                            type_name: type_name.to_string(),
+                            position: na::Vector2::new(
+                                    xml_parsed_attribute(&attributes, "x")?,
+                                    xml_parsed_attribute(&attributes, "y")?
+                                ),
+                            size: na::Vector2::new(
+                                    xml_optional_attribute(&attributes, "width", 0.)?,
+                                    xml_optional_attribute(&attributes, "height", 0.)?
+                                ),
+                            gid: match xml_attribute(&attributes, "gid") {
+                                Some(_) => Some(xml_parsed_attribute(&attributes, "gid")?),
+                                None => None
+                            }
+                        };
+
+                        if let Some(ref mut objects) = objects {
+                            objects.push(object);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::Characters(text) => {
+                if in_data {
+                    if let Some(ref mut layer) = tile_layer {
+                        if let Some(ref mut csv) = layer.csv {
+                            csv.push_str(&text);
+                        }
+                    }
+                }
+            }
+            XmlEvent::EndElement{name} => {
+                match &*name.local_name {
+                    "data" => in_data = false,
+                    "tileset" => in_tileset = false,
+                    "layer" => {
+                        if let Some(layer) = tile_layer.take() {
+                            let data = match layer.csv {
+                                Some(csv) => csv.split(',')
+                                    .map(|gid| gid.trim())
+                                    .filter(|gid| !gid.is_empty())
+                                    .map(|gid| gid.parse::<u32>().map_err(|_| TiledError::InvalidField("data")))
+                                    .collect::<Result<Vec<_>, _>>()?,
+                                None => layer.tiles
+                            };
+
+                            layers.push(RawLayer::Tiles {
+                                name: layer.name,
+                                width: layer.width,
+                                data
+                            });
+                        }
+                    }
+                    "objectgroup" if !in_tileset => {
+                        if let Some(objects) = objects.take() {
+                            layers.push(RawLayer::Objects(objects));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut map = map.ok_or(TiledError::MissingField("map"))?;
+    map.tilesets = tilesets;
+    map.layers = layers;
+    Ok(map)
+}
+
+
+
+pub fn parse_json_tiled_map(source: &str, table: &TilesetTable) -> Result<TiledMap, TiledError> {
+    build_map(parse_json_map(source)?, table)
+}
+
+pub fn parse_xml_tiled_map(source: &str, table: &TilesetTable) -> Result<TiledMap, TiledError> {
+    build_map(parse_xml_map(source)?, table)
+}
+
+/**
+  Loads a .tmx or .tmj map. The format is decided by the file extension
+*/
+pub fn load_tiled_map(path: &Path, table: &TilesetTable) -> Result<TiledMap, TiledError> {
+    let mut source = String::new();
+    File::open(path)?.read_to_string(&mut source)?;
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("tmx") => parse_xml_tiled_map(&source, table),
+        Some("tmj") | Some("json") => parse_json_tiled_map(&source, table),
+        other => Err(TiledError::Unsupported(
+                UnsupportedFeature::FileFormat(other.unwrap_or("").to_string())
+            ))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use na;
+    use super::*;
+
+    fn table() -> TilesetTable {
+        TilesetTable::new()
+            .with_tile("blocks", 0, BlockType::Stone)
+            .with_tile("blocks", 1, BlockType::StoneLadder)
+    }
+
+    const JSON_MAP: &'static str = r#"{
+        "orientation": "orthogonal",
+        "infinite": false,
+        "tilewidth": 16,
+        "tileheight": 16,
+        "tilesets": [{"firstgid": 1, "source": "tiles/blocks.tsj"}],
+        "layers": [
+            {
+                "type": "tilelayer",
+                "name": "walls",
+                "width": 3,
+                "height": 2,
+                "data": [1, 0, 2, 0, 2147483649, 0]
+            },
+            {
+                "type": "objectgroup",
+                "name": "things",
+                "objects": [
+                    {"name": "hall lamp", "type": "lamp", "x": 16, "y": 8, "width": 8, "height": 8},
+                    {"name": "start", "class": "spawn", "x": 0, "y": 0}
+                ]
+            }
+        ]
+    }"#;
+
+    const XML_MAP: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <map version="1.8" orientation="orthogonal" width="3" height="2" tilewidth="32" tileheight="32" infinite="0">
+            <tileset firstgid="1" name="blocks" tilewidth="32" tileheight="32">
+                <tile id="1" type="ladder"/>
+            </tileset>
+            <layer id="1" name="walls" width="3" height="2">
+                <data encoding="csv">
+1,0,2,
+0,1,0
+                </data>
+            </layer>
+            <objectgroup name="things">
+                <object id="1" name="fir" type="tree" gid="1" x="32" y="64" width="32" height="64"/>
+            </objectgroup>
+        </map>
+    "#;
+
+    #[test]
+    fn json_tiles_are_mapped_to_blocks()
+    {
+        let map = parse_json_tiled_map(JSON_MAP, &table()).unwrap();
+
+        assert_eq!(map.blocks, vec!(
+                (BlockType::Stone, na::Vector2::new(0, 0)),
+                (BlockType::StoneLadder, na::Vector2::new(2, 0)),
+                (BlockType::Stone, na::Vector2::new(1, 1)),
+            ));
+    }
+
+    #[test]
+    fn json_objects_are_scaled_to_world_pixels()
+    {
+        let map = parse_json_tiled_map(JSON_MAP, &table()).unwrap();
+
+        assert_eq!(map.objects, vec!(
+                PlacedObject {
+                    kind: ObjectKind::Lamp,
+                    name: "hall lamp".to_string(),
+                    position: na::Vector2::new(32., 16.),
+                    size: na::Vector2::new(16., 16.)
+                },
+                PlacedObject {
+                    kind: ObjectKind::SpawnPoint,
+                    name: "start".to_string(),
+                    position: na::Vector2::new(0., 0.),
+                    size: na::Vector2::new(0., 0.)
+                },
+            ));
+    }
+
+    #[test]
+    fn xml_maps_are_imported()
+    {
+        let map = parse_xml_tiled_map(XML_MAP, &table()).unwrap();
+
+        assert_eq!(map.blocks, vec!(
+                (BlockType::Stone, na::Vector2::new(0, 0)),
+                (BlockType::StoneLadder, na::Vector2::new(2, 0)),
+                (BlockType::Stone, na::Vector2::new(1, 1)),
+            ));
+        assert_eq!(map.objects, vec!(
+                PlacedObject {
+                    kind: ObjectKind::Tree,
+                    name: "fir".to_string(),
+                    position: na::Vector2::new(32., 0.),
+                    size: na::Vector2::new(32., 64.)
+                }
+            ));
+    }
+
+    #[test]
+    fn xml_tile_collision_shapes_are_not_objects()
+    {
+        let source = XML_MAP.replace(
+                r#"<tile id="1" type="ladder"/>"#,
+                r#"<tile id="1" type="ladder">
+                    <objectgroup draworder="index">
+                        <object id="1" type="lamp" x="4" y="0" width="24" height="32"/>
+                    </objectgroup>
+                </tile>"#
+            );
+
+        let map = parse_xml_tiled_map(&source, &table()).unwrap();
+
+        assert_eq!(map.objects.len(), 1);
+        assert_eq!(map.objects[0].kind, ObjectKind::Tree);
+    }
+
+    #[test]
+    fn infinite_maps_are_unsupported()
+    {
+        let source = r#"{
+            "orientation": "orthogonal",
+            "infinite": true,
+            "tilewidth": 16,
+            "tileheight": 16,
+            "tilesets": [{"firstgid": 1, "source": "tiles/blocks.tsj"}],
+            "layers": [
+                {
+                    "type": "tilelayer",
+                    "name": "walls",
+                    "startx": -16,
+                    "starty": 0,
+                    "width": 32,
+                    "height": 16,
+                    "chunks": [
+                        {"x": -16, "y": 0, "width": 16, "height": 16, "data": [1, 0, 2]}
+                    ]
+                }
+            ]
+        }"#;
+
+        match parse_json_tiled_map(source, &table()) {
+            Err(TiledError::Unsupported(UnsupportedFeature::InfiniteMap)) => {},
+            _ => panic!("Expected infinite map error")
+        }
+    }
+
+    #[test]
+    fn isometric_maps_are_unsupported()
+    {
+        let source = XML_MAP.replace("orthogonal", "isometric");
+
+        match parse_xml_tiled_map(&source, &table()) {
+            Err(TiledError::Unsupported(UnsupportedFeature::Orientation(ref orientation)))
+                if orientation == "isometric" => {},
+            _ => panic!("Expected orientation error")
+        }
+    }
+
+    #[test]
+    fn unmapped_tiles_are_reported()
+    {
+        let table = TilesetTable::new().with_tile("blocks", 0, BlockType::Stone);
+
+        match parse_xml_tiled_map(XML_MAP, &table) {
+            Err(TiledError::UnmappedTile{local_id: 1, ref layer, position, ..}) => {
+                assert_eq!(layer, "walls");
+                assert_eq!(position, na::Vector2::new(2, 0));
+            }
+            _ => panic!("Expected unmapped tile error")
+        }
+    }
+}